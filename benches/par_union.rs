@@ -0,0 +1,52 @@
+//! `par_union` against a serial `extend` loop, on 1000 processes of 10000 PFNs each (10M PFNs)
+//! A fifth of the PFNs of each process are shared with other processes, like libraries
+
+use std::{collections::HashSet, hash::BuildHasherDefault};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use procfs::process::Pfn;
+use snap::{par_union, ProcessInfo, TheHash};
+
+const PROCESSES: u64 = 1000;
+const PFNS: u64 = 10_000;
+const SHARED_PFNS: u64 = 2_000;
+const SHARED_POOL: u64 = 20_000;
+
+fn processes_info() -> Vec<ProcessInfo> {
+    (0..PROCESSES)
+        .map(|pid| {
+            // shared PFNs are below SHARED_POOL, each process has half of them in common with the previous one
+            let shared =
+                (0..SHARED_PFNS).map(|idx| Pfn((pid * SHARED_PFNS / 2 + idx) % SHARED_POOL));
+            let private = (0..PFNS - SHARED_PFNS).map(|idx| Pfn(SHARED_POOL + pid * PFNS + idx));
+            ProcessInfo {
+                pid: pid as i32,
+                pfns: shared.chain(private).collect(),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+fn bench_union(c: &mut Criterion) {
+    let processes_info = processes_info();
+
+    let mut group = c.benchmark_group("union of 10M PFNs");
+    group.sample_size(10);
+    group.bench_function("par_union", |b| {
+        b.iter(|| par_union(&processes_info, |p| &p.pfns).len())
+    });
+    group.bench_function("serial extend", |b| {
+        b.iter(|| {
+            let mut union: HashSet<Pfn, BuildHasherDefault<TheHash>> = HashSet::default();
+            for process_info in &processes_info {
+                union.extend(&process_info.pfns);
+            }
+            union.len()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_union);
+criterion_main!(benches);
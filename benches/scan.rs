@@ -0,0 +1,46 @@
+//! Scan of a single process, and merge of scanned processes into a group
+//! Complements `--timing`, which measures whole runs
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use procfs::process::{Pfn, Process};
+use snap::{get_process_info, get_processes_group_info, PhysicalPages, ProcessInfo, ShmsMetadata};
+
+/// 200 processes of 5000 PFNs, 1000 of them shared by all processes
+fn processes_info() -> Vec<ProcessInfo> {
+    (0..200)
+        .map(|pid| ProcessInfo {
+            pid,
+            rss: 5000 * procfs::page_size(),
+            pfns: (0..1000)
+                .chain((0..4000).map(|idx| 1000 + pid as u64 * 4000 + idx))
+                .map(Pfn)
+                .collect(),
+            ..Default::default()
+        })
+        .collect()
+}
+
+fn bench_scan(c: &mut Criterion) {
+    let shms_metadata = ShmsMetadata::default();
+    // without /proc/kpageflags and /proc/kpagecount, like an unprivileged run
+    let physical_pages = PhysicalPages::default();
+
+    c.bench_function("get_process_info of self", |b| {
+        b.iter_batched(
+            || Process::myself().unwrap(),
+            |process| get_process_info(process, &shms_metadata, &physical_pages).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+
+    c.bench_function("get_processes_group_info of 200 processes", |b| {
+        b.iter_batched(
+            processes_info,
+            |processes_info| get_processes_group_info(processes_info, "bench", &shms_metadata),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, bench_scan);
+criterion_main!(benches);
@@ -23,29 +23,39 @@ use log::warn;
 use log::{debug, error, info};
 use procfs::{
     prelude::*,
-    process::{Pfn, Process},
+    process::{MMapPath, PageInfo, Pfn, Process},
     PhysicalPageFlags, Shm,
 };
 use rayon::prelude::*;
-use snap::tmpfs::format_units_MiB;
+use snap::tmpfs::{format_optional_size, format_pfn, format_size, PfnFormat, Unit};
 use snap::{
-    filters, get_process_info, get_smon_info, groups, LargePages, ProcessInfo, ShmsMetadata,
-    SmonInfo, TheHash,
+    filters, get_process_info, get_smon_info, groups, Backend, LargePages, PhysicalPages,
+    ProcessInfo, ShmsMetadata, SmonInfo, TheHash,
 };
 use tabled::Tabled;
 
 use std::{
     collections::{HashMap, HashSet},
     hash::BuildHasherDefault,
+    io::IsTerminal,
     num::NonZeroUsize,
-    sync::{Arc, Mutex},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
 };
 
 use groups::{
-    ProcessSplitter, ProcessSplitterCustomFilter, ProcessSplitterEnvVariable, ProcessSplitterUid,
+    GroupReport, Highlight, OutputFormat, ProcessSplitter, ProcessSplitterAll,
+    ProcessSplitterAncestor, ProcessSplitterByCgroupV1Controller, ProcessSplitterByContainerId,
+    ProcessSplitterByInfo, ProcessSplitterByMemoryThreshold, ProcessSplitterBySessionId,
+    ProcessSplitterCgroup, ProcessSplitterCustomFilter, ProcessSplitterEnvVariable,
+    ProcessSplitterNamespace, ReportsView, SortKey, SystemReport,
 };
 
 use snap::process_tree::ProcessTree;
+use snap::snapshot::Snapshot;
 
 fn main() {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
@@ -95,7 +105,11 @@ Examples:
         #[arg(short, long)]
         threads: Option<usize>,
 
-        #[arg(short, long)]
+        #[arg(
+            short,
+            long,
+            help = "Display statistics for all scanned processes, after the groups"
+        )]
         global_stats: bool,
 
         #[arg(
@@ -105,6 +119,22 @@ Examples:
         )]
         filter: Option<String>,
 
+        #[arg(
+            long = "pid",
+            value_name = "PIDS",
+            value_delimiter = ',',
+            help = "Scan only these pids. Combined with --uid and --filter, processes must match all of them"
+        )]
+        pids: Vec<i32>,
+
+        #[arg(
+            long = "uid",
+            value_name = "UIDS",
+            value_delimiter = ',',
+            help = "Scan only processes of these uids"
+        )]
+        uids: Vec<u32>,
+
         #[arg(
             short,
             long,
@@ -115,11 +145,215 @@ Examples:
         #[arg(short, long, action = clap::ArgAction::Set, default_value_t = false, help = "Force read PFN for shm, even if shm is in swap")]
         force_read_shm: bool,
 
+        #[arg(long, action = clap::ArgAction::Set, default_value_t = true, help = "Exclude kernel threads (PF_KTHREAD flag). They have no user memory")]
+        exclude_kernel_threads: bool,
+
+        #[arg(
+            short,
+            long,
+            value_enum,
+            default_value_t = OutputFormat::Text,
+            help = "Output format for groups reports. Non-text formats only print the reports to stdout"
+        )]
+        output: OutputFormat,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = Unit::MiB,
+            help = "Unit of displayed sizes. 'auto' picks a unit for each value"
+        )]
+        unit: Unit,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = PfnFormat::Hex,
+            help = "Base of displayed and dumped PFNs"
+        )]
+        pfn_format: PfnFormat,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = SortKey::Rss,
+            help = "Order of the groups in reports, descending unless --sort-ascending is set"
+        )]
+        sort: SortKey,
+
+        #[arg(long, help = "Sort groups in ascending order")]
+        sort_ascending: bool,
+
+        #[arg(
+            long,
+            value_name = "SIZE",
+            value_parser = parse_size,
+            help = "Highlight in yellow the groups with a RSS or swap above SIZE, when stdout is a terminal"
+        )]
+        warn: Option<u64>,
+
+        #[arg(
+            long,
+            value_name = "SIZE",
+            value_parser = parse_size,
+            help = "Highlight in red the groups with a RSS or swap above SIZE, when stdout is a terminal"
+        )]
+        crit: Option<u64>,
+
+        #[arg(
+            long,
+            conflicts_with_all = ["load", "diff"],
+            help = "Display RSS and USS of groups as a percentage of MemTotal too"
+        )]
+        percent: bool,
+
+        #[arg(
+            long,
+            value_parser = parse_size,
+            default_value = "0",
+            help = "Hide groups with a smaller RSS, like 100M or 2G. They are still counted in the global stats"
+        )]
+        min_rss: u64,
+
+        #[arg(
+            short,
+            long,
+            help = "Scan again every <WATCH> seconds, clearing the screen between scans"
+        )]
+        watch: Option<u64>,
+
+        #[arg(
+            short,
+            long,
+            help = "Report each process that can't be scanned, and why"
+        )]
+        verbose: bool,
+
+        #[arg(
+            long,
+            help = "Hide progress bars. They are always hidden if stderr is not a terminal"
+        )]
+        no_progress: bool,
+
+        #[arg(
+            short,
+            long,
+            conflicts_with = "watch",
+            help = "Only print a single line with the totals of all processes, in bytes (groups command)"
+        )]
+        quiet: bool,
+
+        #[arg(
+            long,
+            value_name = "PERCENT",
+            default_value_t = 10.0,
+            help = "With --quiet, exit with an error if more than PERCENT of the processes can't be scanned"
+        )]
+        max_scan_errors: f64,
+
+        #[arg(
+            long,
+            conflicts_with_all = ["watch", "quiet", "save"],
+            help = "Print one JSON object per process as soon as it is scanned, without groups (groups command). Memory usage doesn't grow with the number of processes"
+        )]
+        stream: bool,
+
+        #[arg(
+            long,
+            help = "After the scan, enumerate processes again and also scan the new children of scanned processes, forked during the scan"
+        )]
+        follow_children: bool,
+
+        #[arg(
+            long,
+            help = "Also scan memstats itself, its memory is excluded by default"
+        )]
+        include_self: bool,
+
+        #[arg(
+            long,
+            value_name = "SECONDS",
+            help = "Estimate the working set: report pages accessed during SECONDS with idle page tracking (needs CONFIG_IDLE_PAGE_TRACKING)"
+        )]
+        wss: Option<u64>,
+
+        #[arg(
+            long,
+            help = "Read totals from smaps_rollup instead of scanning pages (groups command). Much faster, but RSS and swap of groups count shared pages multiple times, and USS is not computed"
+        )]
+        fast: bool,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = Backend::Auto,
+            conflicts_with = "fast",
+            help = "Read processes memory from pagemap (pages), or smaps (totals, same as --fast). 'auto' falls back to smaps if pagemap PFNs can't be read"
+        )]
+        backend: Backend,
+
+        #[arg(long, help = "Print the duration of each phase of the scan to stderr")]
+        timing: bool,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Save scanned processes and physical pages to a JSON snapshot (groups command)"
+        )]
+        save: Option<PathBuf>,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            conflicts_with_all = ["save", "watch"],
+            help = "Report groups from a JSON snapshot instead of scanning /proc"
+        )]
+        load: Option<PathBuf>,
+
+        #[arg(
+            long,
+            value_name = "PATH",
+            conflicts_with_all = ["load", "diff", "wss"],
+            help = "Read /proc and /sys under PATH instead of /, e.g. a copy taken on another machine. Totals are read from smaps_rollup, pages and shm contents are not available"
+        )]
+        root: Option<PathBuf>,
+
+        #[arg(
+            long,
+            num_args = 2,
+            value_names = ["OLD", "NEW"],
+            conflicts_with_all = ["save", "load", "watch"],
+            help = "Compare groups of 2 JSON snapshots"
+        )]
+        diff: Option<Vec<PathBuf>>,
+
+        #[arg(
+            long,
+            value_name = "MiB",
+            default_value_t = 10,
+            help = "With --diff, highlight groups whose RSS grew by more than this"
+        )]
+        diff_threshold: u64,
+
+        #[arg(
+            long,
+            value_name = "BYTES",
+            help = "Size of the kernel structure for an open file, default depends on the kernel"
+        )]
+        fd_size: Option<u64>,
+
+        #[arg(
+            long,
+            value_name = "BYTES",
+            help = "Size of the kernel structure for a task, default depends on the kernel"
+        )]
+        task_size: Option<u64>,
+
         #[command(subcommand)]
         commands: Commands,
     }
 
-    #[derive(Debug, Subcommand)]
+    #[derive(Debug, Clone, Subcommand)]
     enum Commands {
         #[command(hide = true)]
         GetDbInfo {
@@ -128,32 +362,259 @@ Examples:
         },
         /// Single threaded process scan, can't do multiple groups, but memory efficient
         Single,
+        /// Detailed report of a single process: each mapping, and the processes sharing its pages
+        Explain {
+            #[arg(value_name = "PID")]
+            pid: i32,
+        },
         /// Multi threaded process scan, multiple groups, memory hungry
+        /// Without any split option, split by UID then by ORACLE_SID
         Groups {
-            #[arg(short = 'e', long)]
-            split_env: Option<String>,
+            #[command(flatten)]
+            split: SplitOptions,
+
+            #[arg(
+                long,
+                num_args = 0..=1,
+                default_missing_value = "20",
+                help = "Display the N processes using the most RSS (default 20)"
+            )]
+            top_processes: Option<usize>,
 
-            #[arg(short = 'u', long)]
-            split_uid: bool,
+            #[arg(
+                long,
+                num_args = 0..=1,
+                default_missing_value = "20",
+                help = "Display the N mapped files using the most RSS, shared pages counted once (default 20)"
+            )]
+            top_files: Option<usize>,
+
+            #[arg(
+                long,
+                value_name = "PFN",
+                value_delimiter = ',',
+                value_parser = parse_pfn,
+                help = "Display the processes mapping these physical pages, decimal or 0x-prefixed hexadecimal"
+            )]
+            whomaps: Vec<u64>,
+
+            #[arg(
+                long,
+                help = "Display anonymous pages shared by processes of different users, KSM and zero pages excluded"
+            )]
+            cross_uid: bool,
 
-            #[arg(short = 'p', long, action = clap::ArgAction::Append)]
-            split_pids: Vec<i32>,
+            #[arg(
+                long,
+                num_args = 0..=1,
+                default_missing_value = "20",
+                help = "Display the N mapped files saving the most memory by being shared between processes (default 20)"
+            )]
+            shared_files: Option<usize>,
 
             #[arg(
-                short = 'c',
                 long,
-                help = "Comma separated list of filters, evaluated in order. Can be repeated to create multiple reports"
+                value_name = "GROUP:FILE",
+                value_parser = parse_pfns_dump,
+                help = "Write the sorted PFNs of the group named GROUP to FILE, one per line"
             )]
-            split_custom: Vec<String>,
+            dump_pfns: Vec<(String, PathBuf)>,
         },
     }
 
-    let kernel = procfs::KernelVersion::current().expect("Can't get kernel version");
+    /// Options of the groups command choosing how processes are split into groups
+    #[derive(Debug, Clone, clap::Args)]
+    struct SplitOptions {
+        #[arg(
+            short = 'e',
+            long,
+            action = clap::ArgAction::Append,
+            value_delimiter = ',',
+            help = "Split by environment variables. Multiple variables are combined in a single report"
+        )]
+        split_env: Vec<String>,
+
+        #[arg(short = 'u', long)]
+        split_uid: bool,
+
+        #[arg(long, help = "Split by real primary group")]
+        split_gid: bool,
+
+        #[arg(long, help = "Split by process name")]
+        split_comm: bool,
+
+        #[arg(
+            long,
+            help = "Split by executable path, comm is truncated and shared by unrelated binaries"
+        )]
+        split_exe: bool,
+
+        #[arg(
+            long,
+            help = "Split by process RSS: < 10 MiB, 10 MiB - 100 MiB, 100 MiB - 1 GiB, > 1 GiB"
+        )]
+        split_rss: bool,
+
+        #[arg(long, help = "Split by cgroup v2 path")]
+        split_cgroup: bool,
+
+        #[arg(
+            long,
+            value_name = "CONTROLLER",
+            help = "Split by cgroup v1 path of a controller: memory, cpu, pids..."
+        )]
+        split_cgroup_v1: Option<String>,
+
+        #[arg(
+            long,
+            help = "Split by container, named by image if the docker socket is reachable"
+        )]
+        split_container: bool,
+
+        #[arg(long, help = "Split by session, named after the session leader")]
+        split_session: bool,
+
+        #[arg(
+            long,
+            value_name = "NAMESPACE",
+            help = "Split by namespace: pid, mnt, net, ipc, uts, user, cgroup..."
+        )]
+        split_namespace: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "ROOT_PIDS",
+            num_args = 0..,
+            value_delimiter = ',',
+            help = "Split by top-level ancestor, below the root pids (default 1)"
+        )]
+        split_ancestor: Option<Vec<i32>>,
+
+        #[arg(short = 'p', long, action = clap::ArgAction::Append)]
+        split_pids: Vec<i32>,
+
+        #[arg(
+            short = 'c',
+            long,
+            help = "Comma separated list of filters, evaluated in order. Can be repeated to create multiple reports"
+        )]
+        split_custom: Vec<String>,
+    }
+
+    /// A splitter requested on the command line
+    enum Split {
+        Uid,
+        Gid,
+        Comm,
+        Exe,
+        Rss,
+        Cgroup,
+        CgroupV1(String),
+        Container,
+        Session,
+        Namespace(String),
+        Ancestor(Vec<i32>),
+        Env(Vec<String>),
+        Filter(String),
+    }
+
+    impl SplitOptions {
+        /// Requested splitters, in the order reports are displayed
+        /// Without any split option, split by UID then by ORACLE_SID
+        fn splits(&self) -> Vec<Split> {
+            let mut splits = Vec::new();
+            if self.split_uid {
+                splits.push(Split::Uid);
+            }
+            if self.split_gid {
+                splits.push(Split::Gid);
+            }
+            if self.split_comm {
+                splits.push(Split::Comm);
+            }
+            if self.split_exe {
+                splits.push(Split::Exe);
+            }
+            if self.split_rss {
+                splits.push(Split::Rss);
+            }
+            if self.split_cgroup {
+                splits.push(Split::Cgroup);
+            }
+            splits.extend(self.split_cgroup_v1.clone().map(Split::CgroupV1));
+            if self.split_container {
+                splits.push(Split::Container);
+            }
+            if self.split_session {
+                splits.push(Split::Session);
+            }
+            splits.extend(self.split_namespace.clone().map(Split::Namespace));
+            if let Some(roots) = &self.split_ancestor {
+                let roots = if roots.is_empty() {
+                    vec![1]
+                } else {
+                    roots.clone()
+                };
+                splits.push(Split::Ancestor(roots));
+            }
+            if !self.split_env.is_empty() {
+                splits.push(Split::Env(self.split_env.clone()));
+            }
+            // Waiting for deletion
+            //let mut splitter = ProcessSplitterPids::new(&split_pids);
+            // pid(1),pid(2),pid(3),...
+            let expr = match self.split_pids.len() {
+                0 => None,
+                1 => Some(format!("pid({})", self.split_pids[0])),
+                _ => {
+                    let custom_pids = self
+                        .split_pids
+                        .iter()
+                        .map(|pid| format!("pid({})", pid))
+                        .join(",");
+                    Some(format!("or({})", custom_pids))
+                }
+            };
+            splits.extend(expr.map(Split::Filter));
+            splits.extend(self.split_custom.iter().cloned().map(Split::Filter));
+
+            if splits.is_empty() {
+                // default report: by user, then by Oracle instance
+                splits = vec![Split::Uid, Split::Env(vec!["ORACLE_SID".to_string()])];
+            }
+            splits
+        }
+    }
+
+    let cli = Cli::parse();
+    if let Some(root) = &cli.root {
+        snap::set_root(root.clone());
+    }
+
+    let kernel = std::fs::read_to_string(snap::root_path("/proc/sys/kernel/osrelease"))
+        .ok()
+        .and_then(|release| procfs::KernelVersion::from_str(release.trim()).ok())
+        .expect("Can't get kernel version");
     if kernel < procfs::KernelVersion::new(2, 6, 32) {
         warn!("Untested kernel version {:?}", kernel);
     }
 
-    let cli = Cli::parse();
+    snap::tmpfs::set_unit(cli.unit);
+    snap::tmpfs::set_pfn_format(cli.pfn_format);
+    if let Commands::Groups { dump_pfns, .. } = &cli.commands {
+        groups::set_dump_pfns(dump_pfns.clone());
+    }
+    if std::io::stdout().is_terminal() {
+        groups::set_highlight(Highlight {
+            warn: cli.warn,
+            crit: cli.crit,
+        });
+    }
+    let view = ReportsView {
+        key: cli.sort,
+        ascending: cli.sort_ascending,
+        min_rss: cli.min_rss,
+    };
 
     if let Commands::GetDbInfo { pid } = cli.commands {
         // oracle shouldn't run as root
@@ -172,6 +633,8 @@ Examples:
             large_pages,
             processes,
             pga_size,
+            sga_shms: HashSet::new(),
+            sga_pfns: HashSet::default(),
         };
         let out = serde_json::to_string(&smon_info)
             .expect(&format!("Can't serialize SmonInfo for {sid:?}"));
@@ -221,319 +684,602 @@ Examples:
     debug!("Using {threads} threads");
     debug!("");
 
-    // Main program starts here
-    if uzers::get_effective_uid() != 0 {
-        error!("Run as root");
-        std::process::exit(1);
-    }
-
-    snap::tmpfs::display_tmpfs();
+    if cli.load.is_some() || cli.diff.is_some() {
+        // offline analysis, /proc is not read
+        let Commands::Groups {
+            split,
+            top_processes,
+            top_files,
+            whomaps,
+            cross_uid,
+            shared_files,
+            dump_pfns: _,
+        } = cli.commands.clone()
+        else {
+            error!("--load and --diff can only be used with the groups command");
+            std::process::exit(1);
+        };
 
-    println!("Scanning /proc/kpageflags...");
-    let mut kpageflags = procfs::KPageFlags::new().expect("Can't open /proc/kpageflags");
-    let all_physical_pages: HashMap<Pfn, PhysicalPageFlags> = procfs::iomem()
-        .expect("Can't read iomem")
-        .iter()
-        .filter_map(|(_indent, map)| {
-            if map.name == "System RAM" {
-                Some(map)
-            } else {
-                None
-            }
-        })
-        .map(|map| {
-            let (start, end) = map.get_range().get();
-
-            //let counts = kpagecount
-            //    .get_count_in_range(start, end)
-            //    .expect("Can't read /proc/kpagecount");
-            let flags = kpageflags
-                .get_range_info(start, end)
-                .expect("Can't read /proc/kpagecount");
-            let pfns: Vec<Pfn> = (start.0..end.0).map(Pfn).collect();
-
-            use itertools::izip;
-            let v: Vec<(Pfn, PhysicalPageFlags)> = izip!(pfns, flags).collect();
-
-            v
-        })
-        .flatten()
-        .collect();
-    println!();
-
-    // find smons processes, and for each spawn a new process in the correct context to get database info
-    println!("Scanning Oracle instances...");
-    let mut instances: Vec<SmonInfo> = snap::find_smons()
-        .iter()
-        .filter_map(|(pid, uid, sid, home)| {
-            debug!("Getting DB info for pid={pid}, uid={uid}, sid={sid:?}, home={home:?}");
-            let smon_info = get_smon_info(*pid, *uid, sid.as_os_str(), home.as_os_str());
-
-            match smon_info {
-                Ok(x) => Some(x),
+        // the snapshot may come from another kernel, only use the provided sizes
+        let kernel_sizes = cli.fd_size.zip(cli.task_size);
+        // keep small groups in diffs, so they aren't seen as added or removed
+        let view = if cli.diff.is_some() {
+            ReportsView { min_rss: 0, ..view }
+        } else {
+            view
+        };
+        let report_snapshot = |path: &Path, output: OutputFormat| -> Vec<GroupReport> {
+            let snapshot = match Snapshot::load(path) {
+                Ok(snapshot) => snapshot,
                 Err(e) => {
-                    warn!("Can't get DB info for {sid:?}: {e:?}");
-                    None
+                    error!("{e:?}");
+                    std::process::exit(1);
                 }
-            }
-        })
-        .collect();
-
-    instances.sort_by(|a, b| a.sga_size.cmp(&b.sga_size).reverse());
-
-    #[derive(Tabled)]
-    struct InstanceDisplayRow {
-        sid: String,
-        #[tabled(display_with = "format_units_MiB")]
-        sga: u64,
-        #[tabled(display_with = "format_units_MiB")]
-        pga: u64,
-        processes: u64,
-        large_pages: LargePages,
-    }
+            };
+            let shms_metadata = snapshot.shms_metadata();
 
-    if !instances.is_empty() {
-        println!("Oracle instances (MiB):");
+            report_groups(
+                snapshot.processes_info,
+                &snapshot.tree,
+                &shms_metadata,
+                &snapshot.physical_pages,
+                kernel_sizes,
+                view,
+                output,
+                cli.global_stats,
+                &split,
+                top_processes,
+                top_files,
+                whomaps.clone(),
+                cross_uid,
+                shared_files,
+            )
+        };
 
-        let display_info: Vec<InstanceDisplayRow> = instances
-            .iter()
-            .map(|instance| InstanceDisplayRow {
-                sid: instance.sid.to_string_lossy().to_string(),
-                sga: instance.sga_size,
-                pga: instance.pga_size,
-                processes: instance.processes,
-                large_pages: instance.large_pages,
-            })
-            .collect();
+        if let Some(path) = &cli.load {
+            let reports = report_snapshot(path, cli.output);
+            if let Err(e) = groups::print_reports(&reports, None, cli.output) {
+                error!("Can't print reports: {e:?}");
+            }
+        }
 
-        let mut table = tabled::Table::new(&display_info);
-        table.with(tabled::settings::Style::sharp());
-        println!("{}", table.to_string());
+        if let Some(paths) = &cli.diff {
+            // collect reports without displaying them
+            let old = report_snapshot(&paths[0], OutputFormat::Json);
+            let new = report_snapshot(&paths[1], OutputFormat::Json);
+            let diffs = groups::diff_reports(&old, &new, cli.diff_threshold * 1024 * 1024);
+            if let Err(e) = groups::print_diff(&diffs, cli.output) {
+                error!("Can't print diff: {e:?}");
+            }
+        }
 
-        println!();
-    } else {
-        println!("Can't locate any Oracle instance");
-        println!();
+        return;
     }
 
-    println!("Scanning shm...");
-    // TODO: remove double read
-    for shm in procfs::SharedMemorySegments::current()
-        .expect("Can't read /dev/sysvipc/shm")
-        .0
-    {
-        // dummy scan shm so rss is in sync with number of pages
-        let _x = snap::shm2pfns(&all_physical_pages, &shm, cli.force_read_shm).unwrap();
+    // Main program starts here
+    // a copy of /proc is readable without privileges
+    let permissions = snap::live_root().then(snap::probe_permissions);
+    if let Some(permissions) = permissions.filter(|permissions| !permissions.all()) {
+        display_permissions(&permissions);
     }
 
-    let mut shms_metadata: ShmsMetadata = HashMap::default();
-    for shm in procfs::SharedMemorySegments::current()
-        .expect("Can't read /dev/sysvipc/shm")
-        .0
-    {
-        let x = match snap::shm2pfns(&all_physical_pages, &shm, cli.force_read_shm) {
-            Ok(x) => x,
-            Err(e) => {
-                warn!("Can't read shm {} {e:?}", shm.key);
-                continue;
-            }
-        };
-        shms_metadata.insert(shm, x);
+    // size of kernel structures, to estimate kernel memory used by processes
+    let known_sizes = snap::get_kernel_datastructure_size(kernel);
+    let kernel_sizes = cli
+        .fd_size
+        .or(known_sizes.map(|(fd_size, _task_size)| fd_size))
+        .zip(
+            cli.task_size
+                .or(known_sizes.map(|(_fd_size, task_size)| task_size)),
+        );
+    if kernel_sizes.is_none() {
+        warn!("Unknown kernel structures sizes for kernel {kernel:?}, use --fd-size and --task-size to estimate kernel memory");
     }
 
-    if !shms_metadata.is_empty() {
-        let mut shms: Vec<Shm> = shms_metadata.keys().copied().collect();
-        shms.sort_by(|a, b| a.size.cmp(&b.size).reverse());
+    // probed once, it doesn't change between scans
+    let fast = match cli.backend {
+        // pagemap is only meaningful for running processes
+        _ if !snap::live_root() => true,
+        Backend::Smaps => true,
+        Backend::Pagemap if cli.fast => true,
+        Backend::Pagemap => {
+            if permissions.is_some_and(|permissions| !permissions.pagemap) {
+                error!("Can't read PFNs from pagemap (missing CAP_SYS_ADMIN or kernel lockdown), RSS would be undercounted. Use --backend smaps or auto");
+                std::process::exit(1);
+            }
+            false
+        }
+        Backend::Auto if cli.fast => true,
+        Backend::Auto => {
+            let readable = permissions.is_some_and(|permissions| permissions.pagemap);
+            if !readable {
+                warn!("Can't read PFNs from pagemap (missing CAP_SYS_ADMIN or kernel lockdown), reading totals from smaps_rollup");
+            }
+            !readable
+        }
+    };
+
+    // swap rates of the next scan are computed since this one
+    let mut previous_system: Option<SystemReport> = None;
+    loop {
+        if cli.watch.is_some() {
+            // clear screen
+            print!("\x1B[2J\x1B[1;1H");
+        }
+
+        let text_output = cli.output == OutputFormat::Text && !cli.quiet && !cli.stream;
+        let mut timings = Timings::default();
+        let phase_chrono = std::time::Instant::now();
+
+        let system = SystemReport::current(previous_system.as_ref())
+            .map_err(|e| warn!("Can't read /proc/meminfo: {e:?}"))
+            .ok();
+        if let (Some(system), true) = (&system, text_output) {
+            system.display();
+        }
+        if let (Some(system), true, None) = (&system, cli.percent, &previous_system) {
+            groups::set_percent_of(system.mem_total);
+        }
+
+        if text_output {
+            snap::tmpfs::display_tmpfs();
+
+            println!("Scanning /proc/kpageflags...");
+        }
+        let mut physical_pages = if snap::live_root() {
+            PhysicalPages::read().unwrap_or_else(|e| {
+                warn!("Can't read physical pages, page flags and system RAM won't be reported, private/shared pages are displayed as '-': {e:?}");
+                PhysicalPages::default()
+            })
+        } else {
+            PhysicalPages::default()
+        };
+        physical_pages.numa_nodes = snap::get_numa_nodes_ranges().unwrap_or_else(|e| {
+            warn!("Can't read NUMA nodes, memory per node won't be reported: {e:?}");
+            Vec::new()
+        });
+        physical_pages.swap_devices = snap::get_swap_devices().unwrap_or_else(|e| {
+            warn!("Can't read /proc/swaps, swap per device won't be reported: {e:?}");
+            Vec::new()
+        });
+        if let (Some(seconds), false) = (cli.wss, physical_pages.flags.is_empty()) {
+            if text_output {
+                println!("Sampling idle pages for {seconds} seconds...");
+            }
+            if let Err(e) = physical_pages.sample_idle(std::time::Duration::from_secs(seconds)) {
+                warn!("Can't track idle pages, working set won't be reported: {e:?}");
+            }
+        }
+
+        timings.record("read physical pages", phase_chrono);
+
+        let phase_chrono = std::time::Instant::now();
+        if text_output {
+            println!();
+
+            // find smons processes, and for each spawn a new process in the correct context to get database info
+            println!("Scanning Oracle instances...");
+        }
+        // the database is queried by a subprocess, only possible on the running system
+        let smons = match snap::live_root() {
+            true => snap::find_smons(),
+            false => Vec::new(),
+        };
+        let mut instances: Vec<SmonInfo> = smons
+            .iter()
+            .filter_map(|(pid, uid, sid, home)| {
+                debug!("Getting DB info for pid={pid}, uid={uid}, sid={sid:?}, home={home:?}");
+                let smon_info = get_smon_info(*pid, *uid, sid.as_os_str(), home.as_os_str());
+
+                match smon_info {
+                    Ok(x) => Some(x),
+                    Err(e) => {
+                        warn!("Can't get DB info for {sid:?}: {e:?}");
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        instances.sort_by(|a, b| a.sga_size.cmp(&b.sga_size).reverse());
+        timings.record("find Oracle instances", phase_chrono);
 
         #[derive(Tabled)]
-        struct ShmDisplayRow {
-            key: i32,
-            shmid: u64,
-            #[tabled(display_with = "format_units_MiB")]
-            size: u64,
-            #[tabled(display_with = "format_units_MiB")]
-            rss: u64,
-            pages_4k: String,
-            pages_2M: String,
-            #[tabled(display_with = "format_units_MiB")]
-            swap: u64,
-            #[tabled(rename = "used %")]
-            used: f32,
+        struct InstanceDisplayRow {
             sid: String,
+            #[tabled(display_with = "format_size")]
+            sga: u64,
+            #[tabled(display_with = "format_size")]
+            pga: u64,
+            processes: u64,
+            large_pages: LargePages,
         }
 
-        println!("Shared memory segments (MiB):");
-        let mut shm_display = Vec::new();
-        for shm in &shms {
-            let mut sid_list = Vec::new();
-            for instance in &instances {
-                // we associate each shm with an sid by looking for smon processes
-                let Ok(process) = Process::new(instance.pid) else {
-                    continue;
-                };
-                let Ok(process_info) = get_process_info(process, &shms_metadata) else {
-                    continue;
-                };
+        if !text_output {
+            // nothing displayed
+        } else if !instances.is_empty() {
+            println!("Oracle instances:");
+
+            let display_info: Vec<InstanceDisplayRow> = instances
+                .iter()
+                .map(|instance| InstanceDisplayRow {
+                    sid: instance.sid.to_string_lossy().to_string(),
+                    sga: instance.sga_size,
+                    pga: instance.pga_size,
+                    processes: instance.processes,
+                    large_pages: instance.large_pages,
+                })
+                .collect();
+
+            let mut table = tabled::Table::new(&display_info);
+            table.with(tabled::settings::Style::sharp());
+            println!("{}", table.to_string());
 
-                if process_info.referenced_shms.contains(shm) {
-                    sid_list.push(instance.sid.to_string_lossy().to_string());
+            println!();
+        } else {
+            println!("Can't locate any Oracle instance");
+            println!();
+        }
+
+        if text_output {
+            println!("Scanning shm...");
+        }
+        let phase_chrono = std::time::Instant::now();
+        // TODO: remove double read
+        // may be missing from a copied /proc
+        let shms =
+            match procfs::SharedMemorySegments::from_file(snap::root_path("/proc/sysvipc/shm")) {
+                Ok(shms) => shms.0,
+                Err(e) => {
+                    warn!("Can't read /proc/sysvipc/shm, shm segments are not reported: {e:?}");
+                    Vec::new()
                 }
+            };
+        // segments are attached to read their pages
+        // unprivileged, segments of other users can't be attached (e.g. an SGA with mode 0640)
+        let attach_shms = permissions.is_some_and(|permissions| permissions.shm);
+        let mut unreadable_shms: HashSet<u64> = HashSet::new();
+        for shm in shms.iter().filter(|_| attach_shms) {
+            // dummy scan shm so rss is in sync with number of pages
+            if let Err(e) = snap::shm2pfns(&physical_pages.flags, shm, cli.force_read_shm) {
+                warn!("Can't read shm {} {e:?}", shm.key);
+                unreadable_shms.insert(shm.shmid);
             }
+        }
 
-            // TODO: remove unwrap
-            let (pages_4k, pages_2M) = match shms_metadata.get(shm).unwrap() {
-                Some((_pfns, _swap_pages, pages_4k, pages_2M)) => {
-                    (format!("{}", pages_4k), format!("{}", pages_2M))
+        let mut shms_metadata: ShmsMetadata = HashMap::default();
+        for shm in shms {
+            if !attach_shms {
+                shms_metadata.insert(shm, None);
+                continue;
+            }
+            if unreadable_shms.contains(&shm.shmid) {
+                continue;
+            }
+            let x = match snap::shm2pfns(&physical_pages.flags, &shm, cli.force_read_shm) {
+                Ok(x) => x,
+                Err(e) => {
+                    warn!("Can't read shm {} {e:?}", shm.key);
+                    continue;
                 }
-                None => ("-".into(), "-".into()),
             };
+            shms_metadata.insert(shm, x);
+        }
+        timings.record("read shm", phase_chrono);
 
-            let shm_display_row = ShmDisplayRow {
-                key: shm.key,
-                shmid: shm.shmid,
-                size: shm.size,
-                rss: shm.rss,
-                pages_2M,
-                pages_4k,
-                swap: shm.swap,
-                // USED% can be >100% if size is not aligned with the underling pages: in that case, size < rss+swap
-                used: (shm.rss + shm.swap) as f32 / shm.size as f32 * 100.,
-                sid: sid_list.join(" "),
-            };
-            shm_display.push(shm_display_row);
+        // we associate each shm with an sid by looking for smon processes
+        for instance in &mut instances {
+            if let Err(e) = instance.attach_sga(&shms_metadata, &physical_pages) {
+                warn!("Can't find SGA of {:?}: {e:?}", instance.sid);
+            }
         }
 
-        let mut table = tabled::Table::new(&shm_display);
-        table.with(tabled::settings::Style::sharp());
+        if !text_output {
+            // nothing displayed
+        } else if !shms_metadata.is_empty() {
+            let mut shms: Vec<Shm> = shms_metadata.keys().copied().collect();
+            shms.sort_by(|a, b| a.size.cmp(&b.size).reverse());
+
+            #[derive(Tabled)]
+            struct ShmDisplayRow {
+                key: i32,
+                shmid: u64,
+                #[tabled(display_with = "format_size")]
+                size: u64,
+                #[tabled(display_with = "format_size")]
+                rss: u64,
+                pages_4k: String,
+                huge: String,
+                #[tabled(display_with = "format_size")]
+                swap: u64,
+                #[tabled(rename = "used %")]
+                used: f32,
+                sid: String,
+            }
 
-        println!("{table}");
+            println!("Shared memory segments:");
+            let mut shm_display = Vec::new();
+            for shm in &shms {
+                let mut sid_list = Vec::new();
+                for instance in &instances {
+                    if instance.sga_shms.contains(shm) {
+                        sid_list.push(instance.sid.to_string_lossy().to_string());
+                    }
+                }
 
-        println!();
-    } else {
-        println!("Can't locate any shared memory segment");
-        println!();
-    }
+                // TODO: remove unwrap
+                let (pages_4k, huge) = match shms_metadata.get(shm).unwrap() {
+                    Some((_pfns, _swap_pages, pages_4k, huge_size)) => {
+                        (format!("{}", pages_4k), format_size(&(*huge_size as u64)))
+                    }
+                    None => ("-".into(), "-".into()),
+                };
 
-    // probably incorrect?
-    // size of kernel structures
-    //let current_kernel = procfs::sys::kernel::Version::current().unwrap();
-    //let (fd_size, task_size) =
-    //    snap::get_kernel_datastructure_size(current_kernel).expect("Unknown kernel");
-
-    //let mut kpagecount = procfs::KPageCount::new().expect("Can't open /proc/kpagecount");
-
-    // processes are scanned once and reused to get a more consistent view
-    let mut kernel_processes_count = 0;
-    let all_processes: Vec<Process> = procfs::process::all_processes()
-        .unwrap()
-        .filter_map(|p| match p {
-            Ok(p) => Some(p),
-            Err(e) => match e {
-                procfs::ProcError::NotFound(_) => None,
-                x => {
-                    log::error!("Can't read process {x:?}");
-                    std::process::exit(1);
-                }
-            },
-        })
-        .collect();
-    let all_processes_count = all_processes.len();
-    info!("Total processes {all_processes_count}");
-    let tree = ProcessTree::new(&all_processes);
-
-    // exclude kernel procs
-    let processes: Vec<Process> = all_processes
-        .into_iter()
-        .filter_map(|proc| {
-            if proc.cmdline().ok()?.is_empty() {
-                kernel_processes_count += 1;
-                None
-            } else {
-                Some(proc)
+                let shm_display_row = ShmDisplayRow {
+                    key: shm.key,
+                    shmid: shm.shmid,
+                    size: shm.size,
+                    rss: shm.rss,
+                    huge,
+                    pages_4k,
+                    swap: shm.swap,
+                    // USED% can be >100% if size is not aligned with the underling pages: in that case, size < rss+swap
+                    used: (shm.rss + shm.swap) as f32 / shm.size as f32 * 100.,
+                    sid: sid_list.join(" "),
+                };
+                shm_display.push(shm_display_row);
+            }
+
+            let mut table = tabled::Table::new(&shm_display);
+            table.with(tabled::settings::Style::sharp());
+
+            println!("{table}");
+
+            // pages can be shared by multiple segments, count them once
+            let shm_pfns: HashSet<Pfn, BuildHasherDefault<TheHash>> = shms_metadata
+                .values()
+                .flatten()
+                .flat_map(|(pfns, _swap_pages, _pages_4k, _huge_size)| pfns.iter().copied())
+                .collect();
+            let shm_swap_pages: HashSet<(u64, u64), BuildHasherDefault<TheHash>> = shms_metadata
+                .values()
+                .flatten()
+                .flat_map(|(_pfns, swap_pages, _pages_4k, _huge_size)| swap_pages.iter().copied())
+                .collect();
+            println!(
+                "Total shm: {} in RAM, {} in swap",
+                format_size(&(shm_pfns.len() as u64 * procfs::page_size())),
+                format_size(&(shm_swap_pages.len() as u64 * procfs::page_size()))
+            );
+            if !instances.is_empty() {
+                let sga_total: u64 = instances.iter().map(|instance| instance.sga_size).sum();
+                println!("Total SGA: {}", format_size(&sga_total));
             }
-        })
-        .collect();
-    info!("Excluded {} kernel processes", kernel_processes_count);
 
-    let processes: Vec<Process> = if let Some(filter) = cli.filter {
-        let (f, ate) = filters::parse(&filter).unwrap();
-        if filter.chars().count() != ate {
-            warn!("Ate {ate}, but filter is {} chars", filter.chars().count());
+            println!();
+        } else {
+            println!("Can't locate any shared memory segment");
+            println!();
         }
 
-        let processes: Vec<Process> = processes.into_iter().filter(|p| f.eval(p, &tree)).collect();
-        let processes_count = processes.len();
+        // processes are scanned once and reused to get a more consistent view
+        let phase_chrono = std::time::Instant::now();
+        let mut kernel_processes_count = 0;
+        let all_processes: Vec<Process> = snap::all_processes()
+            .unwrap()
+            .filter_map(|p| match p {
+                Ok(p) => Some(p),
+                Err(e) => match e {
+                    procfs::ProcError::NotFound(_) => None,
+                    x => {
+                        log::error!("Can't read process {x:?}");
+                        std::process::exit(1);
+                    }
+                },
+            })
+            .collect();
+        let all_processes_count = all_processes.len();
+        info!("Total processes {all_processes_count}");
+        let tree = ProcessTree::new(&all_processes);
+
+        // cheap filters first, so that other processes are never read
+        let all_processes: Vec<Process> = all_processes
+            .into_iter()
+            .filter(|p| cli.pids.is_empty() || cli.pids.contains(&p.pid))
+            .filter(|p| cli.uids.is_empty() || p.uid().is_ok_and(|uid| cli.uids.contains(&uid)))
+            .collect();
+        if all_processes.len() != all_processes_count {
+            info!(
+                "Pid/uid filters excluded {} processes",
+                all_processes_count - all_processes.len()
+            );
+        }
 
-        if processes_count == 0 {
-            warn!("Filter excluded all processes");
-            warn!("filter: {filter:?}");
-            return;
+        // exclude kernel threads
+        let processes: Vec<Process> = all_processes
+            .into_iter()
+            .filter(|proc| {
+                // kept if stat can't be read, the scan counts it as vanished or as an error
+                let is_kernel_thread = proc.stat().is_ok_and(|stat| snap::is_kernel_thread(&stat));
+                if is_kernel_thread {
+                    kernel_processes_count += 1;
+                }
+                !(is_kernel_thread && cli.exclude_kernel_threads)
+            })
+            .collect();
+        if text_output {
+            let action = match cli.exclude_kernel_threads {
+                true => "excluded",
+                false => "included",
+            };
+            println!("{kernel_processes_count} kernel threads {action}");
+
+            // pids are the ones of our namespace, but their parents may not be meaningful
+            let other_namespace_count = tree.other_pid_namespace_count();
+            if other_namespace_count > 0 {
+                println!("{other_namespace_count} processes in other PID namespaces (containers), ancestors are not searched outside of their namespace");
+            }
         }
 
-        info!(
-            "Filter excluded {} processes, {} processes remaining",
-            all_processes_count - processes_count,
-            processes_count
-        );
+        let processes: Vec<Process> = if let Some(filter) = &cli.filter {
+            let (f, ate) = filters::parse(filter).unwrap();
+            if filter.chars().count() != ate {
+                warn!("Ate {ate}, but filter is {} chars", filter.chars().count());
+            }
 
-        processes
-    } else {
-        processes
-    };
-    //println!("");
+            let processes: Vec<Process> =
+                processes.into_iter().filter(|p| f.eval(p, &tree)).collect();
+            let processes_count = processes.len();
 
-    if cli.list_processes {
-        println!("       uid        pid comm");
-        println!("==========================");
-        for (uid, pid, comm) in processes
-            .iter()
-            .inspect(|p| {
-                debug!("uid: {:?}", p.uid());
-                debug!("stat: {:?}", p.stat());
-                p.uid().unwrap();
-            })
-            .filter_map(|p| Some((p.uid().ok()?, p.pid, p.stat().ok()?.comm)))
-        {
-            println!("{uid:>10} {pid:>10} {comm}");
+            if processes_count == 0 {
+                warn!("Filter excluded all processes");
+                warn!("filter: {filter:?}");
+                return;
+            }
+
+            info!(
+                "Filter excluded {} processes, {} processes remaining",
+                all_processes_count - processes_count,
+                processes_count
+            );
+
+            processes
+        } else {
+            processes
+        };
+        //println!("");
+
+        if cli.list_processes {
+            println!("       uid        pid comm");
+            println!("==========================");
+            for (uid, pid, comm) in processes
+                .iter()
+                .inspect(|p| {
+                    debug!("uid: {:?}", p.uid());
+                    debug!("stat: {:?}", p.stat());
+                })
+                .filter_map(|p| Some((p.uid().ok()?, p.pid, p.stat().ok()?.comm)))
+            {
+                println!("{uid:>10} {pid:>10} {comm}");
+            }
+            println!();
         }
-        println!();
+
+        timings.record("enumerate processes", phase_chrono);
+
+        let my_process = Process::myself().expect("Can't read /proc/self");
+
+        match cli.commands.clone() {
+            Commands::GetDbInfo { .. } => unreachable!(),
+            Commands::Single => {
+                scan_single(
+                    my_process,
+                    global_chrono,
+                    mem_limit,
+                    processes,
+                    &tree,
+                    &shms_metadata,
+                    &physical_pages,
+                    cli.verbose,
+                    !cli.no_progress,
+                );
+            }
+            Commands::Explain { pid } => {
+                explain(pid, processes, &physical_pages, !cli.no_progress);
+            }
+            Commands::Groups {
+                split,
+                top_processes,
+                top_files,
+                whomaps,
+                cross_uid,
+                shared_files,
+                dump_pfns: _,
+            } => {
+                scan_groups(
+                    my_process,
+                    global_chrono,
+                    mem_limit,
+                    processes,
+                    &tree,
+                    &shms_metadata,
+                    &physical_pages,
+                    kernel_sizes,
+                    view,
+                    system.as_ref(),
+                    &instances,
+                    cli.output,
+                    cli.global_stats,
+                    cli.verbose,
+                    !cli.no_progress,
+                    fast,
+                    cli.quiet.then_some(cli.max_scan_errors),
+                    cli.stream,
+                    cli.follow_children,
+                    // pids of a copied /proc are not ours
+                    cli.include_self || !snap::live_root(),
+                    cli.save.as_deref(),
+                    &split,
+                    top_processes,
+                    top_files,
+                    whomaps,
+                    cross_uid,
+                    shared_files,
+                    &mut timings,
+                );
+            }
+        }
+
+        if cli.timing {
+            timings.display();
+        }
+
+        match cli.watch {
+            Some(interval) => std::thread::sleep(std::time::Duration::from_secs(interval)),
+            None => break,
+        }
+        previous_system = system;
     }
 
-    let my_pid = std::process::id();
-    let my_process = procfs::process::Process::new(my_pid as i32).unwrap();
+    /// Duration of each phase of a scan, for --timing
+    #[derive(Default)]
+    struct Timings {
+        phases: Vec<(&'static str, std::time::Duration)>,
+        /// Processes and PFNs read by the scan, and its duration
+        throughput: Option<(usize, usize, std::time::Duration)>,
+    }
 
-    match cli.commands {
-        Commands::GetDbInfo { .. } => unreachable!(),
-        Commands::Single => {
-            scan_single(
-                my_process,
-                global_chrono,
-                mem_limit,
-                processes,
-                &tree,
-                &shms_metadata,
-            );
+    impl Timings {
+        fn record(&mut self, phase: &'static str, chrono: std::time::Instant) {
+            self.phases.push((phase, chrono.elapsed()));
         }
-        Commands::Groups {
-            split_env,
-            split_uid,
-            split_pids,
-            mut split_custom,
-        } => {
-            split_custom.reverse();
-
-            scan_groups(
-                my_process,
-                global_chrono,
-                mem_limit,
-                processes,
-                &tree,
-                &shms_metadata,
-                split_env,
-                split_uid,
-                split_pids,
-                split_custom,
-            );
+
+        fn record_throughput(
+            &mut self,
+            processes: usize,
+            pfns: usize,
+            elapsed: std::time::Duration,
+        ) {
+            self.throughput = Some((processes, pfns, elapsed));
+        }
+
+        /// On stderr, so that machine-readable outputs are not mixed with timings
+        fn display(&self) {
+            let total: std::time::Duration =
+                self.phases.iter().map(|(_phase, elapsed)| *elapsed).sum();
+            eprintln!("Timings:");
+            for (phase, elapsed) in &self.phases {
+                eprintln!("  {phase:<25} {elapsed:>12.3?}");
+            }
+            eprintln!("  {:<25} {total:>12.3?}", "total");
+            if let Some((processes, pfns, elapsed)) = self.throughput {
+                let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+                eprintln!(
+                    "Scan throughput: {:.0} processes/s, {:.0} PFNs/s",
+                    processes as f64 / seconds,
+                    pfns as f64 / seconds
+                );
+            }
         }
     }
 
@@ -544,9 +1290,13 @@ Examples:
         processes: Vec<Process>,
         _tree: &ProcessTree,
         shms_metadata: &ShmsMetadata,
+        physical_pages: &PhysicalPages,
+        verbose: bool,
+        progress: bool,
     ) {
         let processes_count = processes.len();
         let single_chrono = std::time::Instant::now();
+        let mut scan_errors: Vec<(i32, String)> = Vec::new();
         let hit_memory_limit = Arc::new(Mutex::new(false));
 
         let mut mem_pages: HashSet<Pfn, BuildHasherDefault<TheHash>> = HashSet::default();
@@ -554,10 +1304,8 @@ Examples:
         let mut referenced_shm: HashSet<Shm> = HashSet::new();
         let mut scanned_processes = 0;
 
-        #[allow(unused_variables)]
         let mut vanished = 0;
-        let pb = ProgressBar::new(processes_count as u64);
-        pb.set_style(ProgressStyle::with_template("{msg} {wide_bar} {pos}/{len}").unwrap());
+        let pb = new_progress_bar(processes_count as u64, progress);
         for process in processes {
             let my_rss = my_process.status().unwrap().vmrss.unwrap() / 1024;
             pb.set_message(format!("{my_rss}/{mem_limit} MiB"));
@@ -573,12 +1321,18 @@ Examples:
                 }
                 break;
             }
-            let process_info = match get_process_info(process, shms_metadata) {
-                Ok(info) => info,
-                Err(_) => {
+            let pid = process.pid;
+            let process_info = match get_process_info(process, shms_metadata, physical_pages) {
+                Ok(Some(info)) => info,
+                Ok(None) => continue,
+                Err(e) if e.process_vanished() => {
                     vanished += 1;
                     continue;
                 }
+                Err(e) => {
+                    scan_errors.push((pid, e.to_string()));
+                    continue;
+                }
             };
             scanned_processes += 1;
 
@@ -588,23 +1342,25 @@ Examples:
             pb.inc(1);
         }
         pb.finish_and_clear();
+        report_scan_errors(&scan_errors, verbose);
 
-        let rss = mem_pages.len() as u64 * procfs::page_size() / 1024 / 1024;
-        let swap = swap_pages.len() as u64 * procfs::page_size() / 1024 / 1024;
-        let shm_mem: u64 = referenced_shm.iter().map(|shm| shm.rss).sum::<u64>() / 1024 / 1024;
-        let shm_swap: u64 = referenced_shm.iter().map(|shm| shm.swap).sum::<u64>() / 1024 / 1024;
+        let rss = mem_pages.len() as u64 * procfs::page_size();
+        let swap = swap_pages.len() as u64 * procfs::page_size();
+        let shm_mem: u64 = referenced_shm.iter().map(|shm| shm.rss).sum::<u64>();
+        let shm_swap: u64 = referenced_shm.iter().map(|shm| shm.swap).sum::<u64>();
 
         println!(
-            "{} processes scanned in {:?}",
+            "{} processes scanned in {:?}, {} vanished",
             scanned_processes,
-            single_chrono.elapsed()
+            single_chrono.elapsed(),
+            vanished
         );
-        info!("");
-        info!("Statistics:");
-        info!("mem RSS: {rss}");
-        info!("swap RSS: {swap}");
-        info!("shm mem: {shm_mem}");
-        info!("shm swap: {shm_swap}");
+        println!();
+        println!("Statistics:");
+        println!("mem RSS: {}", format_size(&rss));
+        println!("swap RSS: {}", format_size(&swap));
+        println!("shm mem: {}", format_size(&shm_mem));
+        println!("shm swap: {}", format_size(&shm_swap));
 
         finalize(hit_memory_limit, mem_limit, &my_process, global_chrono);
     }
@@ -616,54 +1372,164 @@ Examples:
         processes: Vec<Process>,
         tree: &ProcessTree,
         shms_metadata: &ShmsMetadata,
-        split_env: Option<String>,
-        split_uid: bool,
-        split_pids: Vec<i32>,
-        mut split_custom: Vec<String>,
+        physical_pages: &PhysicalPages,
+        kernel_sizes: Option<(u64, u64)>,
+        view: ReportsView,
+        system: Option<&SystemReport>,
+        instances: &[SmonInfo],
+        output: OutputFormat,
+        global_stats: bool,
+        verbose: bool,
+        progress: bool,
+        fast: bool,
+        quiet: Option<f64>,
+        stream: bool,
+        follow_children: bool,
+        include_self: bool,
+        save: Option<&Path>,
+        split: &SplitOptions,
+        top_processes: Option<usize>,
+        top_files: Option<usize>,
+        whomaps: Vec<u64>,
+        cross_uid: bool,
+        shared_files: Option<usize>,
+        timings: &mut Timings,
     ) {
         let processes_count = processes.len();
         let hit_memory_limit = Arc::new(Mutex::new(false));
+        let scan_errors: Mutex<Vec<(i32, String)>> = Mutex::new(Vec::new());
+        let vanished = AtomicUsize::new(0);
+        // for the throughput in --timing, also counted when streaming
+        let scanned = AtomicUsize::new(0);
+        let scanned_pfns = AtomicUsize::new(0);
         let chrono = std::time::Instant::now();
-        println!("\nScanning {processes_count} processes");
-        let pb = ProgressBar::new(processes_count as u64);
-        pb.set_style(ProgressStyle::with_template("{msg} {wide_bar} {pos}/{len}").unwrap());
-        let processes_info: Vec<ProcessInfo> = processes
-            .into_par_iter()
-            //.progress_count(processes_count as u64)
-            .filter_map(|proc| {
-                let my_rss = my_process.status().unwrap().vmrss.unwrap() / 1024;
-                pb.set_message(format!("{my_rss}/{mem_limit} MiB"));
-
-                if my_rss > mem_limit {
-                    let mut guard = hit_memory_limit.lock().unwrap();
-                    if !*guard {
-                        warn!("Hit memory limit ({} MiB), try increasing limit or filtering processes", mem_limit);
-                        *guard = true;
-                    }
-                    return None;
+        let text_output = output == OutputFormat::Text && quiet.is_none() && !stream;
+        if text_output {
+            println!("\nScanning {processes_count} processes");
+        }
+        let pb = new_progress_bar(processes_count as u64, progress);
+        let scanned_pids: HashSet<i32> = processes.iter().map(|p| p.pid).collect();
+        let scan_process = |proc: Process| -> Option<ProcessInfo> {
+            let my_rss = my_process.status().unwrap().vmrss.unwrap() / 1024;
+            pb.set_message(format!("{my_rss}/{mem_limit} MiB"));
+
+            if my_rss > mem_limit {
+                let mut guard = hit_memory_limit.lock().unwrap();
+                if !*guard {
+                    warn!(
+                        "Hit memory limit ({} MiB), try increasing limit or filtering processes",
+                        mem_limit
+                    );
+                    *guard = true;
                 }
+                return None;
+            }
 
-                if proc.pid != my_process.pid {
-                    let Ok(info) = get_process_info(proc, shms_metadata) else {return None;};
-                    pb.inc(1);
-                    Some(info)
+            if include_self || proc.pid != my_process.pid {
+                let pid = proc.pid;
+                pb.inc(1);
+                let info = if fast {
+                    snap::get_process_info_fast(proc)
                 } else {
-                    pb.inc(1);
-                    None
+                    get_process_info(proc, shms_metadata, physical_pages)
+                };
+                if let Ok(Some(info)) = &info {
+                    scanned.fetch_add(1, Ordering::Relaxed);
+                    scanned_pfns.fetch_add(info.pfns.len(), Ordering::Relaxed);
                 }
-            })
+                match info {
+                    Ok(Some(info)) if stream => {
+                        // not kept, so memory doesn't grow
+                        print_process_line(&info, !fast && physical_pages.has_counts());
+                        None
+                    }
+                    Ok(info) => info,
+                    Err(e) if e.process_vanished() => {
+                        vanished.fetch_add(1, Ordering::Relaxed);
+                        None
+                    }
+                    Err(e) => {
+                        scan_errors.lock().unwrap().push((pid, e.to_string()));
+                        None
+                    }
+                }
+            } else {
+                pb.inc(1);
+                None
+            }
+        };
+        let mut processes_info: Vec<ProcessInfo> = processes
+            .into_par_iter()
+            //.progress_count(processes_count as u64)
+            .filter_map(&scan_process)
             .collect();
+        if follow_children {
+            let children = find_new_children(&scanned_pids);
+            if !children.is_empty() {
+                info!(
+                    "Scanning {} children forked during the scan",
+                    children.len()
+                );
+                pb.inc_length(children.len() as u64);
+                let children_info: Vec<ProcessInfo> =
+                    children.into_par_iter().filter_map(&scan_process).collect();
+                processes_info.extend(children_info);
+            }
+        }
         pb.finish_and_clear();
+        let scan_errors = scan_errors.into_inner().unwrap();
+        report_scan_errors(&scan_errors, verbose);
+
+        let vanished_processes_count = vanished.into_inner();
+        timings.record("scan processes", chrono);
+        timings.record_throughput(
+            scanned.into_inner(),
+            scanned_pfns.into_inner(),
+            chrono.elapsed(),
+        );
+
+        if stream {
+            finalize(hit_memory_limit, mem_limit, &my_process, global_chrono);
+            return;
+        }
 
-        let vanished_processes_count = processes_count - processes_info.len();
+        let pte_missing_count = processes_info.iter().filter(|p| p.pte_missing).count();
+        if pte_missing_count > 0 {
+            info!(
+                "{pte_missing_count} processes without VmPTE, their page tables are counted as 0"
+            );
+        }
 
-        println!(
-            "Scanned {} processes in {:?}",
-            processes_info.len(),
-            chrono.elapsed()
-        );
-        info!("{} processe(s) vanished", vanished_processes_count);
-        info!("");
+        // e.g. privileges dropped after the probe, pagemap is read with the credentials of its opener
+        let hidden_pfn_pages: u64 = processes_info.iter().map(|p| p.hidden_pfn_pages).sum();
+        if hidden_pfn_pages > 0 {
+            warn!(
+                "{} resident pages without PFN in pagemap, RSS is undercounted by {}. Missing CAP_SYS_ADMIN? Use --backend smaps",
+                hidden_pfn_pages,
+                format_size(&(hidden_pfn_pages * procfs::page_size()))
+            );
+        }
+
+        if text_output {
+            println!(
+                "Scanned {} processes in {:?}, {} vanished",
+                processes_info.len(),
+                chrono.elapsed(),
+                vanished_processes_count
+            );
+            // the observer effect: memory used to scan is taken from the system
+            if let Ok(status) = my_process.status() {
+                println!(
+                    "memstats uses {} (peak {}){}",
+                    format_size(&(status.vmrss.unwrap_or(0) * 1024)),
+                    format_size(&(status.vmhwm.unwrap_or(0) * 1024)),
+                    match include_self {
+                        true => ", included in the groups",
+                        false => ", excluded from the groups",
+                    }
+                );
+            }
+        }
 
         {
             // scan missing SHM
@@ -673,7 +1539,7 @@ Examples:
                     if process_info.unknown_shm.is_empty() {
                         None
                     } else {
-                        Some((process_info.process.pid, process_info.unknown_shm.clone()))
+                        Some((process_info.pid, process_info.unknown_shm.clone()))
                     }
                 })
                 .collect();
@@ -706,58 +1572,786 @@ Examples:
             }
         }
 
+        if text_output && !instances.is_empty() {
+            display_sga_attribution(instances, &processes_info);
+        }
+
+        if let Some(path) = save {
+            match Snapshot::save(path, &processes_info, tree, physical_pages, shms_metadata) {
+                Ok(()) => info!("Snapshot saved to {path:?}"),
+                Err(e) => error!("Can't save snapshot: {e:?}"),
+            }
+        }
+
+        if let Some(max_scan_errors) = quiet {
+            print_totals(
+                processes_info,
+                shms_metadata,
+                physical_pages,
+                scan_errors.len(),
+            );
+            finalize(hit_memory_limit, mem_limit, &my_process, global_chrono);
+
+            let errors_percent = scan_errors.len() as f64 * 100.0 / processes_count.max(1) as f64;
+            if errors_percent > max_scan_errors {
+                error!(
+                    "Can't scan {} of {processes_count} processes ({errors_percent:.1}%), more than {max_scan_errors}%",
+                    scan_errors.len()
+                );
+                std::process::exit(2);
+            }
+            return;
+        }
+
+        let groups_chrono = std::time::Instant::now();
+        let reports = report_groups(
+            processes_info,
+            tree,
+            shms_metadata,
+            physical_pages,
+            kernel_sizes,
+            view,
+            output,
+            global_stats,
+            split,
+            top_processes,
+            top_files,
+            whomaps,
+            cross_uid,
+            shared_files,
+        );
+        if let Err(e) = groups::print_reports(&reports, system, output) {
+            error!("Can't print reports: {e:?}");
+        }
+        timings.record("split and report groups", groups_chrono);
+
+        finalize(hit_memory_limit, mem_limit, &my_process, global_chrono);
+    }
+
+    /// SGA pages can also be found in the pagemap of processes, count them once
+    fn display_sga_attribution(instances: &[SmonInfo], processes_info: &[ProcessInfo]) {
+        #[derive(Tabled)]
+        struct SgaDisplayRow {
+            sid: String,
+            processes: usize,
+            #[tabled(display_with = "format_size")]
+            sga: u64,
+            #[tabled(display_with = "format_size")]
+            double_counted: u64,
+            #[tabled(rename = "private beyond SGA", display_with = "format_size")]
+            private_beyond_sga: u64,
+        }
+
+        let display_info: Vec<SgaDisplayRow> = instances
+            .iter()
+            .map(|instance| {
+                let attribution = snap::sga_attribution(instance, processes_info);
+                SgaDisplayRow {
+                    sid: instance.sid.to_string_lossy().to_string(),
+                    processes: attribution.processes,
+                    sga: attribution.sga,
+                    double_counted: attribution.double_counted,
+                    private_beyond_sga: attribution.private_beyond_sga,
+                }
+            })
+            .collect();
+
+        let mut table = tabled::Table::new(&display_info);
+        table.with(tabled::settings::Style::sharp());
+
+        println!("Oracle instances memory");
+        println!("{table}");
         println!();
-        let processes_info: Vec<ProcessInfo> = if split_uid {
-            let mut splitter = ProcessSplitterUid::new();
-            splitter.split(tree, shms_metadata, processes_info);
-            splitter.display(shms_metadata);
-            splitter.collect_processes()
-        } else {
-            processes_info
+    }
+
+    /// Summary of what can't be read, and what won't be reported because of it, on stderr
+    fn display_permissions(permissions: &snap::Permissions) {
+        #[derive(Tabled)]
+        struct PermissionDisplayRow {
+            source: &'static str,
+            readable: bool,
+            #[tabled(rename = "if not readable")]
+            consequence: &'static str,
+        }
+
+        let rows = [
+            PermissionDisplayRow {
+                source: "/proc/<pid>/pagemap PFNs",
+                readable: permissions.pagemap,
+                consequence:
+                    "totals from smaps_rollup: no USS, shared pages counted multiple times",
+            },
+            PermissionDisplayRow {
+                source: "/proc/kpageflags, /proc/kpagecount",
+                readable: permissions.kpageflags,
+                consequence: "no page flags, private/shared pages and system RAM tables",
+            },
+            PermissionDisplayRow {
+                source: "/proc/iomem addresses",
+                readable: permissions.iomem,
+                consequence: "physical pages can't be read",
+            },
+            PermissionDisplayRow {
+                source: "processes of other users",
+                readable: permissions.other_processes,
+                consequence: "processes of other users are scan errors",
+            },
+            PermissionDisplayRow {
+                source: "SysV shm segments",
+                readable: permissions.shm,
+                consequence: "shm segments are not attached, their pages are unknown",
+            },
+        ];
+        let mut table = tabled::Table::new(rows);
+        table.with(tabled::settings::Style::sharp());
+
+        eprintln!("Running without all privileges (root, or CAP_SYS_ADMIN and CAP_SYS_PTRACE):");
+        eprintln!("{table}");
+        eprintln!();
+    }
+
+    /// Processes that are not in `known_pids`, but descend from one of them
+    /// Short-lived processes have already exited, only the ones still running are found
+    fn find_new_children(known_pids: &HashSet<i32>) -> Vec<Process> {
+        let Ok(all_processes) = snap::all_processes() else {
+            return Vec::new();
         };
+        let mut candidates: Vec<(Process, i32)> = all_processes
+            .flatten()
+            .filter(|p| !known_pids.contains(&p.pid))
+            .filter_map(|p| {
+                let ppid = p.stat().ok()?.ppid;
+                Some((p, ppid))
+            })
+            .collect();
 
-        let processes_info: Vec<ProcessInfo> = if let Some(var) = split_env {
-            let mut splitter = ProcessSplitterEnvVariable::new(var);
-            splitter.split(tree, shms_metadata, processes_info);
-            splitter.display(shms_metadata);
-            splitter.collect_processes()
+        // children of new children are new too, a pass per generation
+        let mut parents = known_pids.clone();
+        let mut children = Vec::new();
+        loop {
+            let (found, remaining): (Vec<_>, Vec<_>) = candidates
+                .into_iter()
+                .partition(|(_p, ppid)| parents.contains(ppid));
+            if found.is_empty() {
+                break;
+            }
+            for (p, _ppid) in found {
+                parents.insert(p.pid);
+                children.push(p);
+            }
+            candidates = remaining;
+        }
+        children
+    }
+
+    /// JSON Lines output of `--stream`, sizes in bytes
+    /// `private_known`: private pages were computed from /proc/kpagecount, otherwise they are left out
+    fn print_process_line(process_info: &ProcessInfo, private_known: bool) {
+        #[derive(serde::Serialize)]
+        struct ProcessLine<'a> {
+            pid: i32,
+            comm: &'a str,
+            uid: u32,
+            rss: u64,
+            anon_rss: u64,
+            file_rss: u64,
+            pss: u64,
+            swap: u64,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            private: Option<u64>,
+            vsz: u64,
+            reserved: u64,
+            pte: u64,
+            fds: usize,
+        }
+
+        let line = ProcessLine {
+            pid: process_info.pid,
+            comm: &process_info.comm,
+            uid: process_info.uid,
+            rss: process_info.rss,
+            anon_rss: process_info.anon_rss,
+            file_rss: process_info.file_rss,
+            pss: process_info.pss,
+            swap: process_info.swap,
+            private: private_known.then(|| process_info.private_pages * procfs::page_size()),
+            vsz: process_info.vsz,
+            reserved: process_info.vsz.saturating_sub(process_info.rss),
+            pte: process_info.pte * 1024,
+            fds: process_info.fds,
+        };
+        match serde_json::to_string(&line) {
+            Ok(line) => println!("{line}"),
+            Err(e) => error!("Can't serialize pid {}: {e:?}", process_info.pid),
+        }
+    }
+
+    /// Single line for scripts: totals of all scanned processes, in bytes
+    fn print_totals(
+        processes_info: Vec<ProcessInfo>,
+        shms_metadata: &ShmsMetadata,
+        physical_pages: &PhysicalPages,
+        scan_errors_count: usize,
+    ) {
+        let processes_count = processes_info.len();
+        let total = snap::get_processes_group_info(processes_info, "total", shms_metadata);
+
+        // processes scanned with `get_process_info_fast` have no pages, use their totals
+        let (rss, swap) = if total.pfns.is_empty() {
+            (total.processes_rss, total.processes_swap)
         } else {
-            processes_info
+            (
+                total.pfns.iter().filter(|pfn| pfn.0 != 0).count() as u64 * procfs::page_size(),
+                total.swap_pages.len() as u64 * procfs::page_size(),
+            )
+        };
+        // pages mapped by a single process, left out if they weren't counted
+        let uss = match physical_pages.has_counts() && !total.pfns.is_empty() {
+            true => format!(" uss={}", total.private_pages * procfs::page_size()),
+            false => String::new(),
         };
 
-        let processes_info = if !split_pids.is_empty() {
-            // Waiting for deletion
-            //let mut splitter = ProcessSplitterPids::new(&split_pids);
+        println!(
+            "processes={processes_count} errors={scan_errors_count} rss={rss}{uss} swap={swap}"
+        );
+    }
 
-            // pid(1),pid(2),pid(3),...
-            let expr = match split_pids.len() {
-                1 => format!("pid({})", split_pids.first().unwrap()),
-                _ => {
-                    let custom_pids = split_pids
-                        .iter()
-                        .map(|pid| format!("pid({})", pid))
-                        .join(",");
-                    format!("or({})", custom_pids)
+    /// Split already scanned processes into groups, and display the reports
+    /// For non-text output, reports are returned instead
+    fn report_groups(
+        mut processes_info: Vec<ProcessInfo>,
+        tree: &ProcessTree,
+        shms_metadata: &ShmsMetadata,
+        physical_pages: &PhysicalPages,
+        kernel_sizes: Option<(u64, u64)>,
+        view: ReportsView,
+        output: OutputFormat,
+        global_stats: bool,
+        split: &SplitOptions,
+        top_processes: Option<usize>,
+        top_files: Option<usize>,
+        whomaps: Vec<u64>,
+        cross_uid: bool,
+        shared_files: Option<usize>,
+    ) -> Vec<GroupReport> {
+        let text_output = output == OutputFormat::Text;
+        if text_output {
+            println!();
+        }
+
+        if let (Some(count), true) = (top_processes, text_output) {
+            display_top_processes(&processes_info, count);
+        }
+
+        if let (Some(count), true) = (top_files, text_output) {
+            display_top_files(&processes_info, count);
+        }
+
+        let shm_attachments = snap::attach_shms(&mut processes_info, shms_metadata);
+        if text_output && !shm_attachments.is_empty() {
+            groups::display_shm_attachments(&shm_attachments);
+        }
+
+        if text_output && !whomaps.is_empty() {
+            display_whomaps(&processes_info, physical_pages, &whomaps);
+        }
+
+        if text_output && cross_uid {
+            display_cross_uid_pages(&processes_info, physical_pages);
+        }
+
+        if let (Some(count), true) = (shared_files, text_output) {
+            display_shared_files(&processes_info, count);
+        }
+
+        if text_output && !physical_pages.flags.is_empty() {
+            groups::display_unaccounted(&processes_info, physical_pages);
+        }
+
+        let mut reports: Vec<GroupReport> = Vec::new();
+        // each splitter groups the processes left by the previous one
+        macro_rules! report {
+            ($splitter:expr) => {{
+                let mut splitter = $splitter;
+                splitter.split(tree, shms_metadata, processes_info);
+                splitter.output(
+                    shms_metadata,
+                    physical_pages,
+                    kernel_sizes,
+                    view,
+                    output,
+                    &mut reports,
+                );
+                splitter.collect_processes()
+            }};
+        }
+        for splitter in split.splits() {
+            processes_info = match splitter {
+                Split::Uid => report!(ProcessSplitterByInfo::uid()),
+                Split::Gid => report!(ProcessSplitterByInfo::gid()),
+                Split::Comm => report!(ProcessSplitterByInfo::comm()),
+                Split::Exe => report!(ProcessSplitterByInfo::exe()),
+                Split::Rss => report!(ProcessSplitterByMemoryThreshold::new(
+                    &ProcessSplitterByMemoryThreshold::DEFAULT_THRESHOLDS
+                )),
+                Split::Cgroup => report!(ProcessSplitterCgroup::new()),
+                Split::CgroupV1(controller) => {
+                    report!(ProcessSplitterByCgroupV1Controller::new(&controller))
+                }
+                Split::Container => report!(ProcessSplitterByContainerId::new()),
+                Split::Session => report!(ProcessSplitterBySessionId::new()),
+                Split::Namespace(namespace) => report!(ProcessSplitterNamespace::new(&namespace)),
+                Split::Ancestor(roots) => report!(ProcessSplitterAncestor::new(&roots)),
+                Split::Env(vars) => report!(ProcessSplitterEnvVariable::new(&vars)),
+                Split::Filter(filter) => {
+                    report!(ProcessSplitterCustomFilter::new(&filter).unwrap())
                 }
             };
+        }
 
-            let mut splitter = ProcessSplitterCustomFilter::new(&expr).unwrap();
+        if global_stats {
+            let mut splitter = ProcessSplitterAll::new();
             splitter.split(tree, shms_metadata, processes_info);
-            splitter.display(shms_metadata);
-            splitter.collect_processes()
+            // the totals are always displayed
+            splitter.output(
+                shms_metadata,
+                physical_pages,
+                kernel_sizes,
+                ReportsView { min_rss: 0, ..view },
+                output,
+                &mut reports,
+            );
+        }
+
+        reports
+    }
+
+    /// Memory of each mapping of a process, and pages shared with other processes
+    fn explain(pid: i32, processes: Vec<Process>, physical_pages: &PhysicalPages, progress: bool) {
+        let page_size = procfs::page_size();
+        let process = match snap::process(pid) {
+            Ok(process) => process,
+            Err(e) => {
+                error!("Can't read pid {pid}: {e:?}");
+                std::process::exit(1);
+            }
+        };
+        let comm = process
+            .stat()
+            .map(|stat| stat.comm)
+            .unwrap_or_else(|_| "?".to_string());
+
+        // all pages of shm mappings, unlike a groups scan
+        let memory_maps = match snap::get_memory_maps_for_process(&process, false) {
+            Ok(memory_maps) => memory_maps,
+            Err(e) => {
+                error!("Can't read memory maps of pid {pid}: {e:?}");
+                std::process::exit(1);
+            }
+        };
+
+        #[derive(Tabled)]
+        struct MappingDisplayRow {
+            address: String,
+            path: String,
+            #[tabled(display_with = "format_size")]
+            vsz: u64,
+            #[tabled(display_with = "format_size")]
+            rss: u64,
+            #[tabled(display_with = "format_optional_size")]
+            private: Option<u64>,
+            #[tabled(display_with = "format_optional_size")]
+            shared: Option<u64>,
+            #[tabled(display_with = "format_size")]
+            swap: u64,
+            #[tabled(display_with = "format_size")]
+            huge: u64,
+        }
+
+        let mut pfns: HashSet<Pfn, BuildHasherDefault<TheHash>> = HashSet::default();
+        let mut mapping_rows = Vec::new();
+        for (memory_map, pages) in &memory_maps {
+            let mut resident: HashSet<Pfn, BuildHasherDefault<TheHash>> = HashSet::default();
+            let mut swap_pages = 0;
+            for page in pages {
+                match page {
+                    PageInfo::MemoryPage(memory_page) => {
+                        let pfn = memory_page.get_page_frame_number();
+                        if pfn.0 != 0 {
+                            resident.insert(pfn);
+                        }
+                    }
+                    PageInfo::SwapPage(_) => swap_pages += 1,
+                }
+            }
+            // a page mapped only once can only be mapped by this process
+            let private = resident
+                .iter()
+                .filter(|pfn| physical_pages.counts.get(*pfn) == Some(&1))
+                .count() as u64;
+            let huge = physical_pages
+                .count_with_flags(&resident, PhysicalPageFlags::HUGE | PhysicalPageFlags::THP);
+
+            let path = match &memory_map.pathname {
+                MMapPath::Path(path) => path.display().to_string(),
+                MMapPath::Vsys(key) => format!("shm key {key} shmid {}", memory_map.inode),
+                other => format!("{other:?}"),
+            };
+            mapping_rows.push(MappingDisplayRow {
+                address: format!("{:x}-{:x}", memory_map.address.0, memory_map.address.1),
+                path,
+                vsz: memory_map.address.1 - memory_map.address.0,
+                rss: resident.len() as u64 * page_size,
+                private: physical_pages.has_counts().then(|| private * page_size),
+                shared: physical_pages
+                    .has_counts()
+                    .then(|| (resident.len() as u64 - private) * page_size),
+                swap: swap_pages * page_size,
+                huge: huge * page_size,
+            });
+            pfns.extend(resident);
+        }
+
+        let mut table = tabled::Table::new(&mapping_rows);
+        table.with(tabled::settings::Style::sharp());
+        println!("Mappings of pid {pid} ({comm})");
+        println!("{table}");
+        println!();
+
+        // only the pages of the explained process are kept
+        let pb = new_progress_bar(processes.len() as u64, progress);
+        pb.set_message("Searching shared pages");
+        let mut sharing: Vec<(i32, String, u64)> = processes
+            .into_par_iter()
+            .filter(|other| other.pid != pid && other.pid != std::process::id() as i32)
+            .filter_map(|other| {
+                pb.inc(1);
+                let memory_maps = snap::get_memory_maps_for_process(&other, false).ok()?;
+                let shared: HashSet<Pfn, BuildHasherDefault<TheHash>> = memory_maps
+                    .iter()
+                    .flat_map(|(_memory_map, pages)| pages)
+                    .filter_map(|page| match page {
+                        PageInfo::MemoryPage(memory_page) => {
+                            Some(memory_page.get_page_frame_number())
+                        }
+                        PageInfo::SwapPage(_) => None,
+                    })
+                    .filter(|pfn| pfns.contains(pfn))
+                    .collect();
+                if shared.is_empty() {
+                    return None;
+                }
+                let comm = other.stat().ok()?.comm;
+                Some((other.pid, comm, shared.len() as u64 * page_size))
+            })
+            .collect();
+        pb.finish_and_clear();
+        sharing.sort_by(|a, b| b.2.cmp(&a.2));
+
+        #[derive(Tabled)]
+        struct SharingDisplayRow {
+            pid: i32,
+            comm: String,
+            #[tabled(display_with = "format_size")]
+            shared: u64,
+        }
+        let sharing_rows: Vec<SharingDisplayRow> = sharing
+            .into_iter()
+            .map(|(pid, comm, shared)| SharingDisplayRow { pid, comm, shared })
+            .collect();
+
+        let mut table = tabled::Table::new(&sharing_rows);
+        table.with(tabled::settings::Style::sharp());
+        println!("Processes sharing pages with pid {pid}");
+        println!("{table}");
+        println!();
+    }
+
+    /// Processes scanned vs total, with memory usage of memstats as message
+    fn new_progress_bar(len: u64, progress: bool) -> ProgressBar {
+        let pb = if progress {
+            // hidden automatically if stderr is not a terminal
+            ProgressBar::new(len)
         } else {
-            processes_info
+            ProgressBar::hidden()
         };
+        pb.set_style(
+            ProgressStyle::with_template("{msg} {wide_bar} {pos}/{len} pids ({eta})").unwrap(),
+        );
+        pb
+    }
 
-        let mut processes_info = processes_info;
-        while let Some(filter) = split_custom.pop() {
-            let mut splitter = ProcessSplitterCustomFilter::new(&filter).unwrap();
-            splitter.split(tree, shms_metadata, processes_info);
-            splitter.display(shms_metadata);
-            processes_info = splitter.collect_processes();
+    fn report_scan_errors(scan_errors: &[(i32, String)], verbose: bool) {
+        if scan_errors.is_empty() {
+            return;
         }
 
-        finalize(hit_memory_limit, mem_limit, &my_process, global_chrono);
+        if verbose {
+            for (pid, e) in scan_errors {
+                warn!("Can't scan pid {pid}: {e}");
+            }
+        } else {
+            warn!(
+                "Can't scan {} processes, use --verbose for details",
+                scan_errors.len()
+            );
+        }
+    }
+
+    fn parse_pfn(value: &str) -> Result<u64, std::num::ParseIntError> {
+        match value.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => value.parse(),
+        }
+    }
+
+    /// Parse GROUP:FILE, the group name may contain colons
+    fn parse_pfns_dump(value: &str) -> Result<(String, PathBuf), String> {
+        match value.rsplit_once(':') {
+            Some((group, file)) if !group.is_empty() && !file.is_empty() => {
+                Ok((group.to_string(), PathBuf::from(file)))
+            }
+            _ => Err(format!("Expected GROUP:FILE, got {value:?}")),
+        }
+    }
+
+    /// Parse a size in bytes, with an optional binary suffix: 512K, 100M, 2GiB
+    fn parse_size(value: &str) -> Result<u64, String> {
+        let value = value.trim();
+        let digits = value
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(value.len());
+        let (number, suffix) = value.split_at(digits);
+        let number: u64 = number
+            .parse()
+            .map_err(|e| format!("Invalid size {value:?}: {e}"))?;
+        let suffix = suffix.to_ascii_uppercase();
+        let shift = match suffix.trim_end_matches("IB").trim_end_matches('B') {
+            "" => 0,
+            "K" => 10,
+            "M" => 20,
+            "G" => 30,
+            "T" => 40,
+            _ => return Err(format!("Unknown size suffix {suffix:?}")),
+        };
+        number
+            .checked_mul(1 << shift)
+            .ok_or_else(|| format!("Size {value:?} is too large"))
+    }
+
+    fn display_whomaps(
+        processes_info: &[ProcessInfo],
+        physical_pages: &PhysicalPages,
+        pfns: &[u64],
+    ) {
+        #[derive(Tabled)]
+        struct WhomapsDisplayRow {
+            pfn: String,
+            #[tabled(rename = "kpagecount")]
+            map_count: String,
+            processes: String,
+        }
+
+        let mappers = snap::pfn_mappers(processes_info);
+        let comms: HashMap<i32, &str> = processes_info
+            .iter()
+            .map(|p| (p.pid, p.comm.as_str()))
+            .collect();
+
+        let display_info: Vec<WhomapsDisplayRow> = pfns
+            .iter()
+            .map(|pfn| {
+                let pfn = Pfn(*pfn);
+                let processes = match mappers.get(&pfn) {
+                    Some(pids) => pids
+                        .iter()
+                        .map(|pid| format!("{pid} ({})", comms[pid]))
+                        .join("\n"),
+                    None => "-".to_string(),
+                };
+                WhomapsDisplayRow {
+                    pfn: format_pfn(pfn.0),
+                    // includes mappings by processes that were not scanned
+                    map_count: match physical_pages.counts.get(&pfn) {
+                        Some(count) => count.to_string(),
+                        None => "-".to_string(),
+                    },
+                    processes,
+                }
+            })
+            .collect();
+
+        let mut table = tabled::Table::new(&display_info);
+        table.with(tabled::settings::Style::sharp());
+
+        println!("Processes mapping physical pages");
+        println!("{table}");
+        println!();
+    }
+
+    fn display_cross_uid_pages(processes_info: &[ProcessInfo], physical_pages: &PhysicalPages) {
+        #[derive(Tabled)]
+        struct CrossUidDisplayRow {
+            uids: String,
+            #[tabled(display_with = "format_size")]
+            mem: u64,
+            processes: String,
+        }
+
+        let comms: HashMap<i32, &str> = processes_info
+            .iter()
+            .map(|p| (p.pid, p.comm.as_str()))
+            .collect();
+        let display_info: Vec<CrossUidDisplayRow> =
+            snap::cross_uid_pages(processes_info, physical_pages)
+                .into_iter()
+                .map(|shared| CrossUidDisplayRow {
+                    uids: shared.uids.iter().join(", "),
+                    mem: shared.pages * procfs::page_size(),
+                    processes: shared
+                        .pids
+                        .iter()
+                        .map(|pid| format!("{pid} ({})", comms[pid]))
+                        .join("\n"),
+                })
+                .collect();
+
+        let mut table = tabled::Table::new(&display_info);
+        table.with(tabled::settings::Style::sharp());
+
+        // e.g. copy-on-write pages of a process that changed its uid after a fork
+        println!("Anonymous pages shared across users");
+        println!("{table}");
+        println!();
+    }
+
+    fn display_shared_files(processes_info: &[ProcessInfo], count: usize) {
+        #[derive(Tabled)]
+        struct SharedFileDisplayRow {
+            path: String,
+            procs: usize,
+            #[tabled(display_with = "format_size")]
+            naive_sum: u64,
+            #[tabled(display_with = "format_size")]
+            unique: u64,
+            #[tabled(display_with = "format_size")]
+            saved: u64,
+        }
+
+        let shared_files = snap::shared_files(processes_info);
+        let page_size = procfs::page_size();
+        let total_saved: u64 = shared_files.iter().map(|f| f.saved_pages()).sum();
+
+        let display_info: Vec<SharedFileDisplayRow> = shared_files
+            .iter()
+            .take(count)
+            .map(|file| SharedFileDisplayRow {
+                path: file.path.to_string_lossy().to_string(),
+                procs: file.procs,
+                naive_sum: file.mapped_pages * page_size,
+                unique: file.unique_pages * page_size,
+                saved: file.saved_pages() * page_size,
+            })
+            .collect();
+
+        let mut table = tabled::Table::new(&display_info);
+        table.with(tabled::settings::Style::sharp());
+
+        println!("Top {count} files by memory saved by sharing");
+        println!("{table}");
+        println!(
+            "Sharing of {} mapped files saves {}",
+            shared_files.len(),
+            format_size(&(total_saved * page_size))
+        );
+        println!();
+    }
+
+    fn display_top_files(processes_info: &[ProcessInfo], count: usize) {
+        #[derive(Tabled)]
+        struct FileDisplayRow {
+            path: String,
+            #[tabled(display_with = "format_size")]
+            rss: u64,
+            procs: usize,
+        }
+
+        // a page of a file mapped by multiple processes is counted once
+        let mut files: HashMap<&Path, (HashSet<Pfn, BuildHasherDefault<TheHash>>, usize)> =
+            HashMap::new();
+        for process_info in processes_info {
+            for (path, pfns) in &process_info.files_pfns {
+                let (file_pfns, procs) = files.entry(path.as_path()).or_default();
+                file_pfns.extend(pfns);
+                *procs += 1;
+            }
+        }
+
+        let mut display_info: Vec<FileDisplayRow> = files
+            .into_iter()
+            .map(|(path, (pfns, procs))| FileDisplayRow {
+                path: path.to_string_lossy().to_string(),
+                rss: pfns.len() as u64 * procfs::page_size(),
+                procs,
+            })
+            .collect();
+        display_info.sort_by(|a, b| b.rss.cmp(&a.rss));
+        display_info.truncate(count);
+
+        let mut table = tabled::Table::new(&display_info);
+        table.with(tabled::settings::Style::sharp());
+
+        println!("Top {count} files by RSS");
+        println!("{table}");
+        println!();
+    }
+
+    fn display_top_processes(processes_info: &[ProcessInfo], count: usize) {
+        #[derive(Tabled)]
+        struct ProcessDisplayRow {
+            pid: i32,
+            comm: String,
+            #[tabled(display_with = "format_size")]
+            rss: u64,
+            #[tabled(display_with = "format_size")]
+            anon_rss: u64,
+            #[tabled(display_with = "format_size")]
+            file_rss: u64,
+            #[tabled(display_with = "format_size")]
+            pss: u64,
+            #[tabled(display_with = "format_size")]
+            swap: u64,
+            #[tabled(display_with = "format_size")]
+            locked: u64,
+            #[tabled(display_with = "format_size")]
+            reserved: u64,
+            fds: usize,
+        }
+
+        let mut display_info: Vec<ProcessDisplayRow> = processes_info
+            .iter()
+            .map(|process_info| ProcessDisplayRow {
+                pid: process_info.pid,
+                comm: process_info.comm.clone(),
+                rss: process_info.rss,
+                anon_rss: process_info.anon_rss,
+                file_rss: process_info.file_rss,
+                pss: process_info.pss,
+                swap: process_info.swap,
+                locked: process_info.locked_pages * procfs::page_size(),
+                reserved: process_info.vsz.saturating_sub(process_info.rss),
+                fds: process_info.fds,
+            })
+            .collect();
+        display_info.sort_by(|a, b| b.rss.cmp(&a.rss));
+        display_info.truncate(count);
+
+        let mut table = tabled::Table::new(&display_info);
+        table.with(tabled::settings::Style::sharp());
+
+        println!("Top {count} processes by RSS");
+        println!("{table}");
+        println!();
     }
 
     fn finalize(
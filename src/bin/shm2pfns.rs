@@ -46,7 +46,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .expect("Can't read /dev/sysvipc/shm")
         .0
     {
-        let (pfns, _swap_pages, _pages_4k, _pages_2M) =
+        let (pfns, _swap_pages, _pages_4k, _huge_size) =
             snap::shm2pfns(&all_physical_pages, &shm, true)
                 .expect("Got an error")
                 .unwrap(); // we can unwrap because we force reads
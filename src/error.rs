@@ -0,0 +1,53 @@
+//! Errors of the scan of processes and Oracle instances
+
+use std::io;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ProcStatsError {
+    /// A file of /proc/<pid> can't be read, or the process exited
+    #[error("Can't read /proc/{0}: {1}")]
+    ProcRead(i32, #[source] io::Error),
+    /// A file was read, but its content is not the expected one
+    #[error("Can't parse {0}")]
+    Parse(String),
+    #[error("Permission denied for {0}, run as root")]
+    PermissionDenied(String),
+    #[error("{0} is not available on this kernel")]
+    KernelFeatureUnavailable(String),
+    /// The subprocess getting the SGA size of an Oracle instance failed
+    #[error("Can't get Oracle instance info: {0}")]
+    SmonSpawn(String),
+}
+
+impl ProcStatsError {
+    /// Convert an error of procfs while reading the files of process `pid`
+    pub fn from_proc(pid: i32, e: procfs::ProcError) -> Self {
+        match e {
+            procfs::ProcError::PermissionDenied(path) => Self::PermissionDenied(match path {
+                Some(path) => path.display().to_string(),
+                None => format!("/proc/{pid}"),
+            }),
+            procfs::ProcError::NotFound(_) => {
+                Self::ProcRead(pid, io::Error::from_raw_os_error(libc::ESRCH))
+            }
+            procfs::ProcError::Incomplete(_) => {
+                Self::ProcRead(pid, io::ErrorKind::UnexpectedEof.into())
+            }
+            procfs::ProcError::Io(e, _) => Self::ProcRead(pid, e),
+            e => Self::Parse(format!("/proc/{pid}: {e}")),
+        }
+    }
+
+    /// Whether the error means that the process exited while it was scanned
+    pub fn process_vanished(&self) -> bool {
+        match self {
+            Self::ProcRead(_, e) => {
+                e.kind() == io::ErrorKind::NotFound
+                    || matches!(e.raw_os_error(), Some(libc::ENOENT) | Some(libc::ESRCH))
+            }
+            _ => false,
+        }
+    }
+}
@@ -4,10 +4,17 @@ use std::ffi::OsString;
 
 use procfs::process::Process;
 
-use crate::process_tree::ProcessTree;
+use crate::{process_tree::ProcessTree, ProcessInfo};
 
 pub trait Filter: std::fmt::Debug {
     fn eval(&self, p: &Process, tree: &ProcessTree) -> bool;
+
+    /// Same as `eval`, but use values already read in `ProcessInfo` instead of reading /proc again
+    fn eval_info(&self, p: &ProcessInfo, tree: &ProcessTree) -> bool {
+        p.process
+            .as_ref()
+            .is_some_and(|process| self.eval(process, tree))
+    }
 }
 
 #[derive(Debug)]
@@ -18,6 +25,9 @@ impl Filter for NotFilter {
     fn eval(&self, p: &Process, tree: &ProcessTree) -> bool {
         !self.inner.eval(p, tree)
     }
+    fn eval_info(&self, p: &ProcessInfo, tree: &ProcessTree) -> bool {
+        !self.inner.eval_info(p, tree)
+    }
 }
 
 #[derive(Debug)]
@@ -44,6 +54,9 @@ impl Filter for AndFilter {
     fn eval(&self, p: &Process, tree: &ProcessTree) -> bool {
         self.children.iter().all(|child| child.eval(p, tree))
     }
+    fn eval_info(&self, p: &ProcessInfo, tree: &ProcessTree) -> bool {
+        self.children.iter().all(|child| child.eval_info(p, tree))
+    }
 }
 
 #[derive(Debug)]
@@ -54,6 +67,9 @@ impl Filter for OrFilter {
     fn eval(&self, p: &Process, tree: &ProcessTree) -> bool {
         self.children.iter().any(|child| child.eval(p, tree))
     }
+    fn eval_info(&self, p: &ProcessInfo, tree: &ProcessTree) -> bool {
+        self.children.iter().any(|child| child.eval_info(p, tree))
+    }
 }
 
 #[derive(Debug)]
@@ -67,6 +83,9 @@ impl Filter for CommFilter {
             Err(_) => false,
         }
     }
+    fn eval_info(&self, p: &ProcessInfo, _: &ProcessTree) -> bool {
+        p.comm == self.comm
+    }
 }
 
 #[derive(Debug)]
@@ -80,6 +99,9 @@ impl Filter for UidFilter {
             Err(_) => false,
         }
     }
+    fn eval_info(&self, p: &ProcessInfo, _: &ProcessTree) -> bool {
+        p.uid == self.uid
+    }
 }
 
 #[derive(Debug)]
@@ -91,6 +113,10 @@ impl Filter for DescendantsFilter {
         let procs = tree.descendants(self.pid);
         procs.contains(&p.pid)
     }
+    fn eval_info(&self, p: &ProcessInfo, tree: &ProcessTree) -> bool {
+        let procs = tree.descendants(self.pid);
+        procs.contains(&p.pid)
+    }
 }
 
 #[derive(Debug)]
@@ -101,6 +127,9 @@ impl Filter for PidFilter {
     fn eval(&self, p: &Process, _: &ProcessTree) -> bool {
         self.pid == p.pid
     }
+    fn eval_info(&self, p: &ProcessInfo, _: &ProcessTree) -> bool {
+        self.pid == p.pid
+    }
 }
 
 #[derive(Debug)]
@@ -114,6 +143,9 @@ impl Filter for EnvironKFilter {
             Err(_) => false,
         }
     }
+    fn eval_info(&self, p: &ProcessInfo, _: &ProcessTree) -> bool {
+        p.environ.get(&OsString::from(&self.key)).is_some()
+    }
 }
 
 #[derive(Debug)]
@@ -128,6 +160,9 @@ impl Filter for EnvironKVFilter {
             Err(_) => false,
         }
     }
+    fn eval_info(&self, p: &ProcessInfo, _: &ProcessTree) -> bool {
+        p.environ.get(&OsString::from(&self.key)) == Some(&OsString::from(&self.value))
+    }
 }
 
 /// uid(0)
@@ -2,19 +2,32 @@ use std::{
     collections::{BTreeMap, HashMap, HashSet},
     ffi::{OsStr, OsString},
     hash::BuildHasherDefault,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::OnceLock,
 };
 
 use anyhow::{bail, Context};
-use indicatif::ProgressBar;
+use indicatif::{ProgressBar, ProgressStyle};
+use itertools::Itertools;
 use log::{debug, warn};
-use procfs::{process::Pfn, Shm};
+use procfs::{
+    process::{Pfn, Process},
+    FromRead, PhysicalPageFlags, Shm,
+};
 use rayon::prelude::*;
+use serde::Serialize;
+use tabled::Tabled;
 
 use crate::{
     filters::{self, Filter},
-    get_processes_group_info, ProcessGroupInfo, ProcessInfo, TheHash,
+    get_processes_group_info, retry_proc, ProcessGroupInfo, ProcessInfo, TheHash,
+};
+use crate::{
+    process_tree::ProcessTree,
+    tmpfs::{format_optional_size, format_pfn, format_size},
+    PhysicalPages, ShmsMetadata,
 };
-use crate::{process_tree::ProcessTree, ShmsMetadata};
 
 pub trait ProcessSplitter<'a> {
     fn name(&self) -> String;
@@ -41,64 +54,46 @@ pub trait ProcessSplitter<'a> {
         debug!("Split by {}: took {:?}", self.name(), chrono.elapsed());
     }
 
-    fn display(&'a self, shm_metadata: &ShmsMetadata) {
+    /// Compute one report per group, in `view` order and without the groups filtered out by `view`
+    /// `kernel_sizes` are the sizes of (file, task) kernel structures, to estimate kernel memory
+    fn reports(
+        &'a self,
+        shm_metadata: &ShmsMetadata,
+        physical_pages: &PhysicalPages,
+        kernel_sizes: Option<(u64, u64)>,
+        view: ReportsView,
+    ) -> Vec<GroupReport> {
         let chrono = std::time::Instant::now();
 
-        use crate::tmpfs::format_units_MiB;
-        use tabled::Tabled;
+        let mut reports: Vec<GroupReport> = Vec::new();
 
-        #[derive(Tabled)]
-        struct ProcessGroupDisplayRow {
-            group_name: String,
-            procs: usize,
-            #[tabled(display_with = "format_units_MiB")]
-            mem_rss: u64,
-            #[tabled(display_with = "format_units_MiB")]
-            mem_anon: u64,
-            #[tabled(display_with = "format_units_MiB")]
-            mem_uss: u64,
-            #[tabled(display_with = "format_units_MiB")]
-            swap_anon: u64,
-            #[tabled(display_with = "format_units_MiB")]
-            swap_rss: u64,
-            #[tabled(display_with = "format_units_MiB")]
-            swap_uss: u64,
-            #[tabled(display_with = "format_units_MiB")]
-            shm_mem: u64,
-            #[tabled(display_with = "format_units_MiB")]
-            shm_swap: u64,
+        // number of groups referencing each page, computed once for all groups
+        // a page is in the USS of a group if no other group references it
+        let mut pfns_groups_count: HashMap<Pfn, u32, BuildHasherDefault<TheHash>> =
+            HashMap::default();
+        let mut swap_groups_count: HashMap<(u64, u64), u32, BuildHasherDefault<TheHash>> =
+            HashMap::default();
+        let mut shm_groups_count: HashMap<Shm, u32> = HashMap::new();
+        for group in self.iter_groups() {
+            for shm in &group.referenced_shm {
+                *shm_groups_count.entry(*shm).or_insert(0) += 1;
+            }
+            for pfn in &group.pfns {
+                *pfns_groups_count.entry(*pfn).or_insert(0) += 1;
+            }
+            for swap_page in &group.swap_pages {
+                *swap_groups_count.entry(*swap_page).or_insert(0) += 1;
+            }
         }
 
-        let mut display_info: Vec<ProcessGroupDisplayRow> = Vec::new();
-
         let pb = ProgressBar::new(self.iter_groups().count() as u64);
+        pb.set_style(ProgressStyle::with_template("{msg} {wide_bar} {pos}/{len} groups").unwrap());
+        pb.set_message(format!("Reports for {}", self.name()));
         for group_1 in self.iter_groups() {
-            let mut other_pfns: HashSet<Pfn, BuildHasherDefault<TheHash>> = HashSet::default();
-            let mut other_swap: HashSet<(u64, u64), BuildHasherDefault<TheHash>> =
-                HashSet::default();
-            let mut other_referenced_shm: HashSet<Shm> = HashSet::new();
-            for group_other in self.iter_groups() {
-                if group_1 != group_other {
-                    other_pfns.par_extend(&group_other.pfns);
-                    other_swap.par_extend(&group_other.swap_pages);
-                    other_referenced_shm.par_extend(&group_other.referenced_shm);
-                }
-            }
-            for (shm, meta) in shm_metadata {
-                match meta {
-                    Some((shm_pfns, _swap_pages, _pages_4k, _pages_2M)) => {
-                        if other_referenced_shm.contains(shm) {
-                            //other_pfns.par_extend(shm_pfns);
-                        }
-                    }
-                    None => (),
-                }
-            }
-
             let mut group_1_pfns = group_1.pfns.clone();
             for (shm, meta) in shm_metadata {
                 match meta {
-                    Some((shm_pfns, _swap_pages, _pages_4k, _pages_2M)) => {
+                    Some((shm_pfns, _swap_pages, _pages_4k, _huge_size)) => {
                         if group_1.referenced_shm.contains(shm) {
                             // TODO: we count shm as rss
                             // do something else?
@@ -108,15 +103,33 @@ pub trait ProcessSplitter<'a> {
                     None => (),
                 }
             }
-            let processes_count = group_1.processes_info.len();
-            let mem_rss = group_1_pfns.len() as u64 * procfs::page_size();
+            let pids: Vec<i32> = group_1.processes_info.iter().map(|p| p.pid).collect();
+            // processes scanned with `get_process_info_fast` have no pages, use their totals
+            let fast = group_1.pfns.is_empty() && group_1.processes_rss > 0;
+            let mem_rss = if fast {
+                group_1.processes_rss
+            } else {
+                group_1_pfns.len() as u64 * procfs::page_size()
+            };
             let mem_anon = group_1.anon_pfns.len() as u64 * procfs::page_size();
-            let mem_uss = group_1_pfns.difference(&other_pfns).count() as u64 * procfs::page_size();
+            let mem_uss = group_1_pfns
+                .iter()
+                .filter(|pfn| pfns_groups_count.get(*pfn) == Some(&1))
+                .count() as u64
+                * procfs::page_size();
 
-            let swap_rss = group_1.swap_pages.len() as u64 * procfs::page_size();
+            let swap_rss = if fast {
+                group_1.processes_swap
+            } else {
+                group_1.swap_pages.len() as u64 * procfs::page_size()
+            };
             let swap_anon = group_1.anon_swap_pages.len() as u64 * procfs::page_size();
-            let swap_uss =
-                group_1.swap_pages.difference(&other_swap).count() as u64 * procfs::page_size();
+            let swap_uss = group_1
+                .swap_pages
+                .iter()
+                .filter(|swap_page| swap_groups_count.get(*swap_page) == Some(&1))
+                .count() as u64
+                * procfs::page_size();
 
             // TODO: no differences for shm?
             let shm_mem: u64 = group_1
@@ -129,35 +142,1040 @@ pub trait ProcessSplitter<'a> {
                 .iter()
                 .map(|shm| shm.swap)
                 .sum::<u64>();
+            // shm are counted in each group that references them, report the ones not shared with other groups
+            let shm_uss: u64 = group_1
+                .referenced_shm
+                .iter()
+                .filter(|shm| shm_groups_count.get(*shm) == Some(&1))
+                .map(|shm| shm.rss)
+                .sum::<u64>();
+
+            let runs = crate::pfn_runs(&group_1.pfns);
 
-            display_info.push(ProcessGroupDisplayRow {
+            // deleted files may be mapped by several processes of the group
+            let deleted_file_pfns: HashSet<Pfn> = group_1
+                .processes_info
+                .iter()
+                .flat_map(|p| &p.files_pfns)
+                .filter(|(path, _pfns)| crate::is_deleted_path(path))
+                .flat_map(|(_path, pfns)| pfns.iter().copied())
+                .collect();
+
+            let mut processes_rss: Vec<u64> =
+                group_1.processes_info.iter().map(|p| p.rss).collect();
+            processes_rss.sort_unstable();
+
+            let mut mem_per_node: BTreeMap<u32, u64> = BTreeMap::new();
+            for pfn in &group_1.pfns {
+                if let Some(node) = physical_pages.numa_node(*pfn) {
+                    *mem_per_node.entry(node).or_insert(0) += procfs::page_size();
+                }
+            }
+
+            let mut swap_per_device: BTreeMap<String, u64> = BTreeMap::new();
+            for (swap_type, _offset) in &group_1.swap_pages {
+                *swap_per_device
+                    .entry(physical_pages.swap_device(*swap_type))
+                    .or_insert(0) += procfs::page_size();
+            }
+
+            reports.push(GroupReport {
+                splitter: self.name(),
                 group_name: group_1.name.clone(),
-                procs: processes_count,
+                procs: pids.len(),
+                pids,
                 mem_rss,
+                mem_pss: group_1.pss,
+                mem_vsz: group_1.vsz,
+                mem_reserved: group_1.vsz.saturating_sub(group_1.processes_rss),
                 mem_anon,
+                mem_page_cache: group_1.page_cache_rss,
+                mem_deleted_file: deleted_file_pfns.len() as u64 * procfs::page_size(),
+                mem_active: physical_pages.idle_sampled.then(|| {
+                    // pfn 0 stands for pages that aren't present
+                    let resident_pages = group_1.pfns.iter().filter(|pfn| pfn.0 != 0).count();
+                    (resident_pages as u64).saturating_sub(
+                        physical_pages.count_with_flags(&group_1.pfns, PhysicalPageFlags::IDLE),
+                    ) * procfs::page_size()
+                }),
                 mem_uss,
+                mem_private: physical_pages
+                    .has_counts()
+                    .then(|| group_1.private_pages * procfs::page_size()),
+                mem_shared: physical_pages
+                    .has_counts()
+                    .then(|| group_1.shared_pages * procfs::page_size()),
+                mem_per_node,
+                processes_rss: ProcessesRssReport::new(&processes_rss),
                 swap_rss,
                 swap_anon,
+                // a page is either resident or swapped, a page in the swap cache is only counted as resident
+                anon_total: mem_anon + swap_anon,
                 swap_uss,
+                swap_per_device,
                 shm_mem,
+                shm_uss,
                 shm_swap,
+                shms: group_1.referenced_shm.iter().copied().collect(),
+                pte: group_1.pte,
+                fds: group_1.fds,
+                maps: group_1.num_maps,
+                kernel_mem: kernel_sizes.map(|(fd_size, task_size)| {
+                    group_1.fds as u64 * fd_size + group_1.processes_info.len() as u64 * task_size
+                }),
+                swap_pages: group_1.swap_pages.len(),
+                mem_device: group_1.device_pages * procfs::page_size(),
+                pfn_runs: runs.len(),
+                largest_run: runs.iter().map(|(_start, len)| *len).max().unwrap_or(0)
+                    * procfs::page_size(),
+                page_flags: PageFlagsReport {
+                    hugetlb: physical_pages
+                        .count_with_flags(&group_1.pfns, PhysicalPageFlags::HUGE)
+                        * procfs::page_size(),
+                    thp: physical_pages.count_with_flags(&group_1.pfns, PhysicalPageFlags::THP)
+                        * procfs::page_size(),
+                    ksm: physical_pages.count_with_flags(&group_1.pfns, PhysicalPageFlags::KSM)
+                        * procfs::page_size(),
+                    zero: physical_pages
+                        .count_with_flags(&group_1.pfns, PhysicalPageFlags::ZERO_PAGE)
+                        * procfs::page_size(),
+                    dirty: physical_pages.count_with_flags(&group_1.pfns, PhysicalPageFlags::DIRTY)
+                        * procfs::page_size(),
+                    locked: physical_pages.count_with_flags(
+                        &group_1.pfns,
+                        PhysicalPageFlags::MLOCKED | PhysicalPageFlags::UNEVICTABLE,
+                    ) * procfs::page_size(),
+                },
             });
             pb.inc(1);
         }
         pb.finish_and_clear();
 
-        // sort by mem RSS
-        display_info.sort_by(|a, b| b.mem_rss.cmp(&a.mem_rss));
+        view.apply(&mut reports);
 
-        let mut table = tabled::Table::new(&display_info);
-        table.with(tabled::settings::Style::sharp());
+        debug!(
+            "Reports for split by {}: {:?}",
+            self.name(),
+            chrono.elapsed()
+        );
+        reports
+    }
 
-        println!("{}", self.name());
-        println!("{table}");
+    fn display(
+        &'a self,
+        shm_metadata: &ShmsMetadata,
+        physical_pages: &PhysicalPages,
+        kernel_sizes: Option<(u64, u64)>,
+        view: ReportsView,
+    ) {
+        let chrono = std::time::Instant::now();
+
+        let reports = self.reports(shm_metadata, physical_pages, kernel_sizes, view);
+        display_reports(&self.name(), &reports);
 
         debug!("Display split by {}: {:?}", self.name(), chrono.elapsed());
         println!();
     }
+
+    /// Write the PFNs of the groups selected with `set_dump_pfns`
+    fn dump_pfns(&'a self) {
+        let Some(dumps) = DUMP_PFNS.get() else {
+            return;
+        };
+        for group in self.iter_groups() {
+            for (_name, path) in dumps.iter().filter(|(name, _path)| *name == group.name) {
+                if group.pfns.is_empty() && group.processes_rss > 0 {
+                    warn!(
+                        "No pages for group {:?}, PFNs are not read by the smaps backend",
+                        group.name
+                    );
+                }
+                match write_pfns(path, &group.pfns) {
+                    Ok(()) => debug!(
+                        "Wrote {} PFNs of {:?} to {path:?}",
+                        group.pfns.len(),
+                        group.name
+                    ),
+                    Err(e) => warn!("Can't write PFNs of {:?} to {path:?}: {e:?}", group.name),
+                }
+            }
+        }
+    }
+
+    /// Display as a table, or accumulate reports for a machine-readable output
+    fn output(
+        &'a self,
+        shm_metadata: &ShmsMetadata,
+        physical_pages: &PhysicalPages,
+        kernel_sizes: Option<(u64, u64)>,
+        view: ReportsView,
+        format: OutputFormat,
+        reports: &mut Vec<GroupReport>,
+    ) {
+        self.dump_pfns();
+        match format {
+            OutputFormat::Text => self.display(shm_metadata, physical_pages, kernel_sizes, view),
+            OutputFormat::Json | OutputFormat::Csv | OutputFormat::Prometheus => {
+                reports.extend(self.reports(shm_metadata, physical_pages, kernel_sizes, view))
+            }
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+    /// node_exporter textfile collector format
+    Prometheus,
+}
+
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    Rss,
+    Uss,
+    Swap,
+    Name,
+    /// Number of processes
+    Pids,
+}
+
+/// Order of the groups in reports, and which ones are displayed
+#[derive(Copy, Clone, Debug)]
+pub struct ReportsView {
+    pub key: SortKey,
+    pub ascending: bool,
+    /// Groups with a smaller RSS are left out of the reports
+    pub min_rss: u64,
+}
+
+impl ReportsView {
+    pub fn apply(&self, reports: &mut Vec<GroupReport>) {
+        reports.retain(|report| report.mem_rss >= self.min_rss);
+        reports.sort_by(|a, b| {
+            let ordering = match self.key {
+                SortKey::Rss => a.mem_rss.cmp(&b.mem_rss),
+                SortKey::Uss => a.mem_uss.cmp(&b.mem_uss),
+                SortKey::Swap => a.swap_rss.cmp(&b.swap_rss),
+                SortKey::Name => a.group_name.cmp(&b.group_name),
+                SortKey::Pids => a.procs.cmp(&b.procs),
+            };
+            if self.ascending {
+                ordering
+            } else {
+                ordering.reverse()
+            }
+        });
+    }
+}
+
+/// RSS or swap of a group above which its row is highlighted, in bytes
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Highlight {
+    pub warn: Option<u64>,
+    pub crit: Option<u64>,
+}
+
+static HIGHLIGHT: OnceLock<Highlight> = OnceLock::new();
+
+/// Highlight groups in tables, only the first call has an effect
+/// Not called when stdout isn't a terminal, so that escape codes aren't written to files or pipes
+pub fn set_highlight(highlight: Highlight) {
+    if HIGHLIGHT.set(highlight).is_err() {
+        warn!("Highlight is already set");
+    }
+}
+
+impl Highlight {
+    fn color(&self, report: &GroupReport) -> Option<tabled::settings::Color> {
+        let over = |threshold: Option<u64>| {
+            threshold.is_some_and(|threshold| report.mem_rss.max(report.swap_rss) >= threshold)
+        };
+        if over(self.crit) {
+            Some(tabled::settings::Color::FG_RED)
+        } else if over(self.warn) {
+            Some(tabled::settings::Color::FG_YELLOW)
+        } else {
+            None
+        }
+    }
+}
+
+/// (group name, file) of `--dump-pfns`
+static DUMP_PFNS: OnceLock<Vec<(String, PathBuf)>> = OnceLock::new();
+
+/// Write the PFNs of the groups with these names to files when they are output, only the first call has an effect
+pub fn set_dump_pfns(dumps: Vec<(String, PathBuf)>) {
+    if DUMP_PFNS.set(dumps).is_err() {
+        warn!("PFN dumps are already set");
+    }
+}
+
+/// Sorted PFNs, one per line in the base from `--pfn-format`, e.g. for page-types
+fn write_pfns(
+    path: &Path,
+    pfns: &HashSet<Pfn, BuildHasherDefault<TheHash>>,
+) -> std::io::Result<()> {
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+    for pfn in pfns.iter().map(|pfn| pfn.0).sorted_unstable() {
+        writeln!(writer, "{}", format_pfn(pfn))?;
+    }
+    writer.flush()
+}
+
+/// MemTotal, in bytes
+static PERCENT_OF: OnceLock<u64> = OnceLock::new();
+
+/// Display RSS and USS in tables as a percentage of `total` as well, only the first call has an effect
+pub fn set_percent_of(total: u64) {
+    if PERCENT_OF.set(total).is_err() {
+        warn!("Percent total is already set");
+    }
+}
+
+fn format_size_percent(size: &u64) -> String {
+    match PERCENT_OF.get() {
+        Some(&total) if total > 0 => format!(
+            "{} ({:.1}%)",
+            format_size(size),
+            *size as f64 * 100. / total as f64
+        ),
+        _ => format_size(size),
+    }
+}
+
+/// Display reports of a splitter as tables: memory, then page flags
+pub fn display_reports(name: &str, reports: &[GroupReport]) {
+    let mut table = tabled::Table::new(reports);
+    table.with(tabled::settings::Style::sharp());
+    if let Some(highlight) = HIGHLIGHT.get() {
+        for (i, report) in reports.iter().enumerate() {
+            if let Some(color) = highlight.color(report) {
+                // first row is the header
+                table.with(
+                    tabled::settings::Modify::new(tabled::settings::object::Rows::single(i + 1))
+                        .with(color),
+                );
+            }
+        }
+    }
+
+    println!("{name}");
+    println!("{table}");
+
+    #[derive(Tabled)]
+    struct PageFlagsDisplayRow {
+        group_name: String,
+        #[tabled(inline)]
+        page_flags: PageFlagsReport,
+        pfn_runs: usize,
+        #[tabled(display_with = "format_size")]
+        largest_run: u64,
+        #[tabled(rename = "device", display_with = "format_size")]
+        mem_device: u64,
+        maps: usize,
+        #[tabled(rename = "maps/proc")]
+        maps_per_process: usize,
+        #[tabled(rename = "avg map rss", display_with = "format_size")]
+        average_map_rss: u64,
+    }
+    let page_flags_rows: Vec<PageFlagsDisplayRow> = reports
+        .iter()
+        .map(|report| PageFlagsDisplayRow {
+            group_name: report.group_name.clone(),
+            page_flags: report.page_flags.clone(),
+            pfn_runs: report.pfn_runs,
+            largest_run: report.largest_run,
+            mem_device: report.mem_device,
+            maps: report.maps,
+            maps_per_process: report.maps / report.procs.max(1),
+            average_map_rss: report.mem_rss / report.maps.max(1) as u64,
+        })
+        .collect();
+    let mut table = tabled::Table::new(&page_flags_rows);
+    table.with(tabled::settings::Style::sharp());
+
+    println!("{name} (page flags)");
+    println!("{table}");
+
+    // groups sharing each segment
+    let mut shm_groups: BTreeMap<(i32, u64), (Shm, Vec<&str>)> = BTreeMap::new();
+    for report in reports {
+        for shm in &report.shms {
+            shm_groups
+                .entry((shm.key, shm.shmid))
+                .or_insert((*shm, Vec::new()))
+                .1
+                .push(&report.group_name);
+        }
+    }
+    if shm_groups.is_empty() {
+        return;
+    }
+
+    #[derive(Tabled)]
+    struct ShmGroupsDisplayRow {
+        key: i32,
+        shmid: u64,
+        #[tabled(display_with = "format_size")]
+        rss: u64,
+        groups: String,
+    }
+    let shm_rows: Vec<ShmGroupsDisplayRow> = shm_groups
+        .into_values()
+        .map(|(shm, groups)| ShmGroupsDisplayRow {
+            key: shm.key,
+            shmid: shm.shmid,
+            rss: shm.rss,
+            groups: groups.join("\n"),
+        })
+        .collect();
+    let mut table = tabled::Table::new(&shm_rows);
+    table.with(tabled::settings::Style::sharp());
+
+    println!("{name} (shm)");
+    println!("{table}");
+}
+
+/// Processes attached to each shm segment, from `attach_shms`
+pub fn display_shm_attachments(attachments: &HashMap<Shm, Vec<i32>>) {
+    #[derive(Tabled)]
+    struct ShmAttachmentDisplayRow {
+        key: i32,
+        shmid: u64,
+        #[tabled(display_with = "format_size")]
+        rss: u64,
+        procs: usize,
+        #[tabled(display_with = "format_pids")]
+        pids: Vec<i32>,
+    }
+
+    let mut rows: Vec<ShmAttachmentDisplayRow> = attachments
+        .iter()
+        .map(|(shm, pids)| ShmAttachmentDisplayRow {
+            key: shm.key,
+            shmid: shm.shmid,
+            rss: shm.rss,
+            procs: pids.len(),
+            pids: pids.clone(),
+        })
+        .collect();
+    rows.sort_by(|a, b| b.rss.cmp(&a.rss));
+
+    let mut table = tabled::Table::new(&rows);
+    table.with(tabled::settings::Style::sharp());
+
+    println!("Shared memory attachments");
+    println!("{table}");
+    println!();
+}
+
+/// System memory from /proc/meminfo, in bytes, and swap activity from /proc/vmstat
+#[derive(Serialize, Debug)]
+pub struct SystemReport {
+    pub mem_total: u64,
+    /// kernel >= 3.14
+    pub mem_available: Option<u64>,
+    pub swap_total: u64,
+    pub swap_free: u64,
+    /// Pages swapped in and out since boot
+    pub pswpin: Option<u64>,
+    pub pswpout: Option<u64>,
+    /// Seconds since boot, from /proc/uptime
+    pub uptime: Option<f64>,
+    /// Swap in and out, in bytes per second, since the previous report or since boot
+    pub swap_in_rate: Option<u64>,
+    pub swap_out_rate: Option<u64>,
+    #[serde(skip)]
+    rates_since_boot: bool,
+}
+
+impl SystemReport {
+    /// Swap rates are computed since `previous`, e.g. the previous scan with --watch, or since boot
+    pub fn current(previous: Option<&SystemReport>) -> procfs::ProcResult<Self> {
+        let meminfo = procfs::Meminfo::from_file(crate::root_path("/proc/meminfo"))?;
+
+        let vmstat: HashMap<String, u64> =
+            std::fs::read_to_string(crate::root_path("/proc/vmstat"))
+                .map(|content| {
+                    content
+                        .lines()
+                        .filter_map(|line| {
+                            let (key, value) = line.split_once(' ')?;
+                            Some((key.to_string(), value.trim().parse().ok()?))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+        let pswpin = vmstat.get("pswpin").copied();
+        let pswpout = vmstat.get("pswpout").copied();
+        let uptime: Option<f64> = std::fs::read_to_string(crate::root_path("/proc/uptime"))
+            .ok()
+            .and_then(|content| content.split_whitespace().next()?.parse().ok());
+
+        // counters and uptime are 0 at boot
+        let (pswpin_before, pswpout_before, uptime_before) = match previous {
+            Some(previous) => (previous.pswpin, previous.pswpout, previous.uptime),
+            None => (Some(0), Some(0), Some(0.)),
+        };
+        let rate = |now: Option<u64>, before: Option<u64>| -> Option<u64> {
+            let elapsed = uptime? - uptime_before?;
+            if elapsed <= 0. {
+                return None;
+            }
+            let pages = now?.saturating_sub(before?);
+            Some((pages as f64 * procfs::page_size() as f64 / elapsed) as u64)
+        };
+
+        Ok(Self {
+            mem_total: meminfo.mem_total,
+            mem_available: meminfo.mem_available,
+            swap_total: meminfo.swap_total,
+            swap_free: meminfo.swap_free,
+            pswpin,
+            pswpout,
+            uptime,
+            swap_in_rate: rate(pswpin, pswpin_before),
+            swap_out_rate: rate(pswpout, pswpout_before),
+            rates_since_boot: previous.is_none(),
+        })
+    }
+
+    pub fn display(&self) {
+        println!(
+            "MemTotal: {}, MemAvailable: {}, SwapTotal: {}, SwapFree: {}",
+            format_size(&self.mem_total),
+            format_optional_size(&self.mem_available),
+            format_size(&self.swap_total),
+            format_size(&self.swap_free)
+        );
+        if let (Some(swap_in_rate), Some(swap_out_rate)) = (self.swap_in_rate, self.swap_out_rate) {
+            let since = match self.rates_since_boot {
+                true => "since boot",
+                false => "since previous scan",
+            };
+            println!(
+                "Swap in: {}/s, swap out: {}/s ({since})",
+                format_size(&swap_in_rate),
+                format_size(&swap_out_rate)
+            );
+        }
+        println!();
+    }
+}
+
+/// Print reports accumulated for a machine-readable output
+/// `system` is only known for live scans, not for snapshots
+pub fn print_reports(
+    reports: &[GroupReport],
+    system: Option<&SystemReport>,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Text => (),
+        OutputFormat::Json => {
+            #[derive(Serialize)]
+            struct JsonOutput<'a> {
+                #[serde(skip_serializing_if = "Option::is_none")]
+                system: Option<&'a SystemReport>,
+                groups: &'a [GroupReport],
+            }
+
+            let out = serde_json::to_string(&JsonOutput {
+                system,
+                groups: reports,
+            })
+            .context("Can't serialize reports")?;
+            println!("{out}");
+        }
+        OutputFormat::Csv => {
+            #[derive(Serialize)]
+            struct CsvRow<'a> {
+                splitter: &'a str,
+                name: &'a str,
+                pid_count: usize,
+                rss_bytes: u64,
+                uss_bytes: u64,
+                swap_bytes: u64,
+                pte_kb: u64,
+                fds: usize,
+            }
+
+            let mut writer = csv::Writer::from_writer(std::io::stdout());
+            for report in reports {
+                writer.serialize(CsvRow {
+                    splitter: &report.splitter,
+                    name: &report.group_name,
+                    pid_count: report.procs,
+                    rss_bytes: report.mem_rss,
+                    uss_bytes: report.mem_uss,
+                    swap_bytes: report.swap_rss,
+                    pte_kb: report.pte,
+                    fds: report.fds,
+                })?;
+            }
+            writer.flush()?;
+        }
+        OutputFormat::Prometheus => {
+            let metrics: [(&str, &str, fn(&GroupReport) -> u64); 19] = [
+                ("processes", "Number of processes", |r| r.procs as u64),
+                ("rss_bytes", "Resident memory", |r| r.mem_rss),
+                ("pss_bytes", "Proportional set size", |r| r.mem_pss),
+                ("anon_bytes", "Anonymous resident memory", |r| r.mem_anon),
+                (
+                    "page_cache_bytes",
+                    "Mapped page cache, shared proportionally",
+                    |r| r.mem_page_cache,
+                ),
+                (
+                    "deleted_file_bytes",
+                    "Resident memory of deleted mapped files",
+                    |r| r.mem_deleted_file,
+                ),
+                ("uss_bytes", "Memory not shared with other groups", |r| {
+                    r.mem_uss
+                }),
+                ("swap_bytes", "Swapped memory", |r| r.swap_rss),
+                (
+                    "swap_uss_bytes",
+                    "Swapped memory not shared with other groups",
+                    |r| r.swap_uss,
+                ),
+                ("shm_bytes", "Resident memory of attached shm", |r| {
+                    r.shm_mem
+                }),
+                ("shm_swap_bytes", "Swapped memory of attached shm", |r| {
+                    r.shm_swap
+                }),
+                ("pte_bytes", "Page tables", |r| r.pte * 1024),
+                ("fds", "Open file descriptors", |r| r.fds as u64),
+                ("dirty_bytes", "Dirty memory", |r| r.page_flags.dirty),
+                ("hugetlb_bytes", "Reserved huge pages (hugetlbfs)", |r| {
+                    r.page_flags.hugetlb
+                }),
+                ("thp_bytes", "Transparent huge pages", |r| r.page_flags.thp),
+                (
+                    "vsz_bytes",
+                    "Sum of the virtual sizes of the processes",
+                    |r| r.mem_vsz,
+                ),
+                (
+                    "anon_total_bytes",
+                    "Anonymous memory, resident or swapped",
+                    |r| r.anon_total,
+                ),
+                (
+                    "reserved_bytes",
+                    "Virtual size of the processes not resident in memory",
+                    |r| r.mem_reserved,
+                ),
+            ];
+
+            for (name, help, value) in metrics {
+                println!("# HELP memstats_group_{name} {help}");
+                println!("# TYPE memstats_group_{name} gauge");
+                for report in reports {
+                    println!(
+                        "memstats_group_{name}{{splitter=\"{}\",group=\"{}\"}} {}",
+                        escape_label_value(&report.splitter),
+                        escape_label_value(&report.group_name),
+                        value(report)
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Escape a Prometheus label value: backslash, double quote and line feed
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Display system RAM mapped by the scanned processes, and what the other pages are used for
+pub fn display_unaccounted(processes_info: &[ProcessInfo], physical_pages: &PhysicalPages) {
+    #[derive(Tabled)]
+    struct RamDisplayRow {
+        category: &'static str,
+        pages: u64,
+        #[tabled(display_with = "format_size")]
+        mem: u64,
+    }
+
+    // first matching category wins
+    let categories = [
+        ("free", PhysicalPageFlags::BUDDY),
+        ("slab", PhysicalPageFlags::SLAB),
+        ("reserved", PhysicalPageFlags::RESERVED),
+        ("huge pages", PhysicalPageFlags::HUGE),
+        ("anonymous", PhysicalPageFlags::ANON),
+        ("page cache", PhysicalPageFlags::LRU),
+    ];
+
+    let accounted: HashSet<Pfn, BuildHasherDefault<TheHash>> = processes_info
+        .iter()
+        .flat_map(|process_info| process_info.pfns.iter().copied())
+        .filter(|pfn| physical_pages.flags.contains_key(pfn))
+        .collect();
+
+    let mut unaccounted = vec![0u64; categories.len() + 1];
+    for (pfn, flags) in &physical_pages.flags {
+        if accounted.contains(pfn) {
+            continue;
+        }
+        let idx = categories
+            .iter()
+            .position(|(_name, category_flags)| flags.intersects(*category_flags))
+            .unwrap_or(categories.len());
+        unaccounted[idx] += 1;
+    }
+
+    let page_size = procfs::page_size();
+    let mut rows = vec![RamDisplayRow {
+        category: "processes",
+        pages: accounted.len() as u64,
+        mem: accounted.len() as u64 * page_size,
+    }];
+    for (name, pages) in categories
+        .iter()
+        .map(|(name, _flags)| *name)
+        .chain(std::iter::once("other"))
+        .zip(unaccounted)
+    {
+        rows.push(RamDisplayRow {
+            category: name,
+            pages,
+            mem: pages * page_size,
+        });
+    }
+    let total = physical_pages.flags.len() as u64;
+    rows.push(RamDisplayRow {
+        category: "total",
+        pages: total,
+        mem: total * page_size,
+    });
+
+    let mut table = tabled::Table::new(&rows);
+    table.with(tabled::settings::Style::sharp());
+
+    println!("System RAM");
+    println!("{table}");
+    println!();
+}
+
+/// Difference of a group between two reports, matched by splitter and group name
+#[derive(Tabled, Serialize, Debug)]
+pub struct GroupDiff {
+    #[tabled(skip)]
+    pub splitter: String,
+    pub group_name: String,
+    #[tabled(display_with = "format_size")]
+    pub mem_rss_old: u64,
+    #[tabled(display_with = "format_size")]
+    pub mem_rss_new: u64,
+    #[tabled(display_with = "format_delta")]
+    pub mem_rss_delta: i64,
+    #[tabled(display_with = "format_delta")]
+    pub mem_uss_delta: i64,
+    #[tabled(display_with = "format_delta")]
+    pub swap_rss_delta: i64,
+    #[tabled(display_with = "format_pids")]
+    pub pids_added: Vec<i32>,
+    #[tabled(display_with = "format_pids")]
+    pub pids_removed: Vec<i32>,
+    /// RSS grew more than the threshold
+    #[tabled(rename = "grew", display_with = "format_flag")]
+    pub over_threshold: bool,
+}
+
+/// Compare reports of 2 snapshots, sorted by RSS growth
+/// A group missing from a snapshot is considered empty
+pub fn diff_reports(old: &[GroupReport], new: &[GroupReport], threshold: u64) -> Vec<GroupDiff> {
+    let mut pairs: BTreeMap<(&str, &str), (Option<&GroupReport>, Option<&GroupReport>)> =
+        BTreeMap::new();
+    for report in old {
+        pairs
+            .entry((&report.splitter, &report.group_name))
+            .or_default()
+            .0 = Some(report);
+    }
+    for report in new {
+        pairs
+            .entry((&report.splitter, &report.group_name))
+            .or_default()
+            .1 = Some(report);
+    }
+
+    let mut diffs: Vec<GroupDiff> = pairs
+        .into_iter()
+        .map(|((splitter, group_name), (old, new))| {
+            let field = |report: Option<&GroupReport>, f: fn(&GroupReport) -> u64| {
+                report.map(f).unwrap_or(0) as i64
+            };
+            let old_pids: HashSet<i32> = old.iter().flat_map(|r| r.pids.iter().copied()).collect();
+            let new_pids: HashSet<i32> = new.iter().flat_map(|r| r.pids.iter().copied()).collect();
+
+            let mem_rss_delta = field(new, |r| r.mem_rss) - field(old, |r| r.mem_rss);
+            GroupDiff {
+                splitter: splitter.to_string(),
+                group_name: group_name.to_string(),
+                mem_rss_old: field(old, |r| r.mem_rss) as u64,
+                mem_rss_new: field(new, |r| r.mem_rss) as u64,
+                mem_rss_delta,
+                mem_uss_delta: field(new, |r| r.mem_uss) - field(old, |r| r.mem_uss),
+                swap_rss_delta: field(new, |r| r.swap_rss) - field(old, |r| r.swap_rss),
+                pids_added: new_pids.difference(&old_pids).copied().sorted().collect(),
+                pids_removed: old_pids.difference(&new_pids).copied().sorted().collect(),
+                over_threshold: mem_rss_delta > threshold as i64,
+            }
+        })
+        .collect();
+    diffs.sort_by(|a, b| b.mem_rss_delta.cmp(&a.mem_rss_delta));
+
+    diffs
+}
+
+/// Print differences between 2 snapshots, as a table or in a machine-readable format
+pub fn print_diff(diffs: &[GroupDiff], format: OutputFormat) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Text => {
+            let mut table = tabled::Table::new(diffs);
+            table.with(tabled::settings::Style::sharp());
+
+            println!("Groups diff");
+            println!("{table}");
+        }
+        OutputFormat::Json => {
+            let out = serde_json::to_string(diffs).context("Can't serialize diff")?;
+            println!("{out}");
+        }
+        OutputFormat::Csv => {
+            #[derive(Serialize)]
+            struct CsvRow<'a> {
+                splitter: &'a str,
+                name: &'a str,
+                rss_old_bytes: u64,
+                rss_new_bytes: u64,
+                rss_delta_bytes: i64,
+                uss_delta_bytes: i64,
+                swap_delta_bytes: i64,
+                pids_added: usize,
+                pids_removed: usize,
+                over_threshold: bool,
+            }
+
+            let mut writer = csv::Writer::from_writer(std::io::stdout());
+            for diff in diffs {
+                writer.serialize(CsvRow {
+                    splitter: &diff.splitter,
+                    name: &diff.group_name,
+                    rss_old_bytes: diff.mem_rss_old,
+                    rss_new_bytes: diff.mem_rss_new,
+                    rss_delta_bytes: diff.mem_rss_delta,
+                    uss_delta_bytes: diff.mem_uss_delta,
+                    swap_delta_bytes: diff.swap_rss_delta,
+                    pids_added: diff.pids_added.len(),
+                    pids_removed: diff.pids_removed.len(),
+                    over_threshold: diff.over_threshold,
+                })?;
+            }
+            writer.flush()?;
+        }
+        OutputFormat::Prometheus => bail!("Prometheus output is not supported for diff"),
+    }
+
+    Ok(())
+}
+
+fn format_delta(delta: &i64) -> String {
+    let sign = if *delta < 0 { "-" } else { "+" };
+    format!("{sign}{}", format_size(&delta.unsigned_abs()))
+}
+
+fn format_pids(pids: &[i32]) -> String {
+    const MAX_DISPLAYED: usize = 10;
+    if pids.len() > MAX_DISPLAYED {
+        format!(
+            "{} ... ({} pids)",
+            pids.iter().take(MAX_DISPLAYED).join(" "),
+            pids.len()
+        )
+    } else {
+        pids.iter().join(" ")
+    }
+}
+
+fn format_flag(flag: &bool) -> String {
+    match flag {
+        true => "*".to_string(),
+        false => String::new(),
+    }
+}
+
+fn format_per_device(per_device: &BTreeMap<String, u64>) -> String {
+    per_device
+        .iter()
+        .map(|(device, size)| format!("{device}: {}", format_size(size)))
+        .join("\n")
+}
+
+fn format_per_node(per_node: &BTreeMap<u32, u64>) -> String {
+    per_node
+        .iter()
+        .map(|(node, size)| format!("{node}: {}", format_size(size)))
+        .join("\n")
+}
+
+#[derive(Tabled, Serialize, Debug)]
+pub struct GroupReport {
+    #[tabled(skip)]
+    pub splitter: String,
+    pub group_name: String,
+    pub procs: usize,
+    #[tabled(skip)]
+    pub pids: Vec<i32>,
+    #[tabled(display_with = "format_size_percent")]
+    pub mem_rss: u64,
+    #[tabled(display_with = "format_size")]
+    pub mem_pss: u64,
+    /// Sum of the virtual sizes of the processes, a large VSZ with a small RSS means sparse mappings
+    #[tabled(display_with = "format_size")]
+    pub mem_vsz: u64,
+    /// Virtual size never faulted in, e.g. sparse allocations or guard regions: VSZ minus RSS of each process
+    #[tabled(display_with = "format_size")]
+    pub mem_reserved: u64,
+    #[tabled(display_with = "format_size")]
+    pub mem_anon: u64,
+    /// Page cache mapped by the processes, shared pages are attributed proportionally
+    #[tabled(display_with = "format_size")]
+    pub mem_page_cache: u64,
+    /// Resident pages of mapped files that were deleted, a common source of leaks on tmpfs
+    #[tabled(display_with = "format_size")]
+    pub mem_deleted_file: u64,
+    /// Working set: pages accessed during the `--wss` interval, None if idle pages were not sampled
+    #[tabled(display_with = "format_optional_size")]
+    pub mem_active: Option<u64>,
+    #[tabled(display_with = "format_size_percent")]
+    pub mem_uss: u64,
+    /// Pages mapped once system wide, and pages mapped multiple times: None if /proc/kpagecount wasn't read
+    #[tabled(display_with = "format_optional_size")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mem_private: Option<u64>,
+    #[tabled(display_with = "format_optional_size")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mem_shared: Option<u64>,
+    #[tabled(display_with = "format_per_node")]
+    pub mem_per_node: BTreeMap<u32, u64>,
+    #[tabled(
+        rename = "procs_rss min/med/p95/max",
+        display_with = "format_processes_rss"
+    )]
+    pub processes_rss: ProcessesRssReport,
+    #[tabled(display_with = "format_size")]
+    pub swap_anon: u64,
+    /// Anonymous memory, resident or swapped: a partly swapped group doesn't look smaller than a resident one
+    #[tabled(display_with = "format_size")]
+    pub anon_total: u64,
+    #[tabled(display_with = "format_size")]
+    pub swap_rss: u64,
+    #[tabled(display_with = "format_size")]
+    pub swap_uss: u64,
+    /// Swap of the group on each swap device or file, e.g. zram or disk
+    #[tabled(display_with = "format_per_device")]
+    pub swap_per_device: BTreeMap<String, u64>,
+    #[tabled(display_with = "format_size")]
+    pub shm_mem: u64,
+    #[tabled(display_with = "format_size")]
+    pub shm_uss: u64,
+    #[tabled(display_with = "format_size")]
+    pub shm_swap: u64,
+    /// Attached shm segments
+    #[tabled(skip)]
+    pub shms: Vec<Shm>,
+    #[tabled(skip)]
+    pub pte: u64,
+    #[tabled(skip)]
+    pub fds: usize,
+    /// Number of mappings of the processes
+    #[tabled(skip)]
+    pub maps: usize,
+    /// Estimated memory of kernel structures for open files and tasks, None if sizes are unknown
+    #[tabled(display_with = "format_optional_size")]
+    pub kernel_mem: Option<u64>,
+    #[tabled(skip)]
+    pub swap_pages: usize,
+    /// Mapped memory outside of System RAM (device memory), not counted in mem_rss
+    #[tabled(skip)]
+    pub mem_device: u64,
+    /// Number of runs of contiguous physical pages, higher is more fragmented
+    #[tabled(skip)]
+    pub pfn_runs: usize,
+    /// Size of the largest run of contiguous physical pages
+    #[tabled(skip)]
+    pub largest_run: u64,
+    #[tabled(skip)]
+    pub page_flags: PageFlagsReport,
+}
+
+/// Distribution of RSS of the processes of a group, to spot a single large process, in bytes
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ProcessesRssReport {
+    pub min: u64,
+    pub median: u64,
+    pub p95: u64,
+    pub max: u64,
+}
+
+impl ProcessesRssReport {
+    /// `sorted_rss` must be sorted
+    fn new(sorted_rss: &[u64]) -> Self {
+        let percentile = |p: usize| match sorted_rss.len() {
+            0 => 0,
+            len => sorted_rss[(len - 1) * p / 100],
+        };
+        Self {
+            min: percentile(0),
+            median: percentile(50),
+            p95: percentile(95),
+            max: percentile(100),
+        }
+    }
+}
+
+fn format_processes_rss(report: &ProcessesRssReport) -> String {
+    [report.min, report.median, report.p95, report.max]
+        .iter()
+        .map(format_size)
+        .join("\n")
+}
+
+/// Group memory by physical page flags (from /proc/kpageflags), in bytes
+#[derive(Tabled, Serialize, Debug, Clone, Default)]
+pub struct PageFlagsReport {
+    /// reserved huge pages (hugetlbfs, SHM_HUGETLB, MAP_HUGETLB), never swapped nor reclaimed
+    #[tabled(display_with = "format_size")]
+    pub hugetlb: u64,
+    /// transparent huge pages, can be split and reclaimed like regular pages
+    #[tabled(display_with = "format_size")]
+    pub thp: u64,
+    /// pages merged by KSM
+    #[tabled(display_with = "format_size")]
+    pub ksm: u64,
+    /// zero page, mapped by reading anonymous memory before writing to it
+    #[tabled(display_with = "format_size")]
+    pub zero: u64,
+    /// dirty pages, to be written back before they can be reclaimed
+    #[tabled(display_with = "format_size")]
+    pub dirty: u64,
+    /// mlocked or unevictable pages, can't be reclaimed
+    #[tabled(display_with = "format_size")]
+    pub locked: u64,
 }
 
 pub struct ProcessSplitterCustomFilter {
@@ -216,7 +1234,7 @@ impl<'a> ProcessSplitter<'a> for ProcessSplitterCustomFilter {
     ) {
         for (group_name, filter) in self.names.iter().zip(&self.filters) {
             let some_processes = processes
-                .extract_if(|p| filter.eval(&p.process, tree))
+                .extract_if(|p| filter.eval_info(p, tree))
                 .collect();
             let process_group_info =
                 get_processes_group_info(some_processes, group_name, shms_metadata);
@@ -240,50 +1258,82 @@ impl<'a> ProcessSplitter<'a> for ProcessSplitterCustomFilter {
     }
 }
 
+/// Split by the values of one or more environment variables
 pub struct ProcessSplitterEnvVariable {
-    var: OsString,
-    groups: HashMap<Option<OsString>, ProcessGroupInfo>,
+    vars: Vec<OsString>,
+    groups: HashMap<Vec<Option<OsString>>, ProcessGroupInfo>,
 }
 impl ProcessSplitterEnvVariable {
-    pub fn new<S: AsRef<OsStr>>(var: S) -> Self {
+    pub fn new<S: AsRef<OsStr>, I: IntoIterator<Item = S>>(vars: I) -> Self {
         Self {
             groups: HashMap::new(),
-            var: var.as_ref().to_os_string(),
+            vars: vars
+                .into_iter()
+                .map(|var| var.as_ref().to_os_string())
+                .collect(),
         }
     }
 }
 
 impl<'a> ProcessSplitter<'a> for ProcessSplitterEnvVariable {
     type GroupIter<'b: 'a> =
-        std::collections::hash_map::Values<'a, Option<OsString>, ProcessGroupInfo>;
+        std::collections::hash_map::Values<'a, Vec<Option<OsString>>, ProcessGroupInfo>;
 
     fn name(&self) -> String {
-        format!("environment variable {}", self.var.to_string_lossy())
+        let vars = self.vars.iter().map(|var| var.to_string_lossy()).join(", ");
+        if self.vars.len() == 1 {
+            format!("environment variable {vars}")
+        } else {
+            format!("environment variables {vars}")
+        }
     }
     fn __split(
         &mut self,
         _tree: &ProcessTree,
         shms_metadata: &ShmsMetadata,
-        mut processes: Vec<ProcessInfo>,
+        processes: Vec<ProcessInfo>,
     ) {
-        let sids: HashSet<Option<OsString>> = processes
-            .par_iter()
-            .map(|p| p.environ.get(&self.var).cloned())
-            .collect();
-
-        let mut groups: HashMap<Option<OsString>, ProcessGroupInfo> = HashMap::new();
-        for sid in sids {
-            let some_processes: Vec<ProcessInfo> = processes
-                .extract_if(|p| p.environ.get(&self.var) == sid.as_ref())
+        let mut processes_by_values: HashMap<Vec<Option<OsString>>, Vec<ProcessInfo>> =
+            HashMap::new();
+        for process_info in processes {
+            let values: Vec<Option<OsString>> = self
+                .vars
+                .iter()
+                .map(|var| process_info.environ.get(var).cloned())
                 .collect();
-            let name = format!(
-                "{:?}",
-                sid.as_ref().map(|os| os.to_string_lossy().to_string())
-            );
-            let process_group_info = get_processes_group_info(some_processes, &name, shms_metadata);
-            groups.insert(sid, process_group_info);
+            processes_by_values
+                .entry(values)
+                .or_default()
+                .push(process_info);
         }
-        self.groups = groups;
+
+        // groups are independent, compute them in parallel
+        self.groups = processes_by_values
+            .into_par_iter()
+            .map(|(values, some_processes)| {
+                let values_display: Vec<String> = values
+                    .iter()
+                    .map(|value| {
+                        format!(
+                            "{:?}",
+                            value.as_ref().map(|os| os.to_string_lossy().to_string())
+                        )
+                    })
+                    .collect();
+                let name = if self.vars.len() == 1 {
+                    values_display[0].clone()
+                } else {
+                    self.vars
+                        .iter()
+                        .zip(values_display)
+                        .map(|(var, value)| format!("{}={value}", var.to_string_lossy()))
+                        .join(" ")
+                };
+                let process_group_info =
+                    get_processes_group_info(some_processes, &name, shms_metadata);
+                (values, process_group_info)
+            })
+            .collect();
     }
     fn iter_groups<'x>(&'a self) -> Self::GroupIter<'a> {
         self.groups.values()
@@ -296,41 +1346,133 @@ impl<'a> ProcessSplitter<'a> for ProcessSplitterEnvVariable {
     }
 }
 
-pub struct ProcessSplitterUid {
-    groups: BTreeMap<u32, ProcessGroupInfo>,
+/// Executable path of a process, or why it is unknown
+///
+/// "(deleted)" only when the binary is gone: the link target is marked deleted, or missing while the process
+/// still runs. Other users' processes can't be read without CAP_SYS_PTRACE, they are "(unreadable)"
+fn exe_label(process: &Process, comm: &str) -> String {
+    match retry_proc(|| process.exe()) {
+        Ok(exe) => {
+            let exe = exe.display().to_string();
+            match exe.strip_suffix(" (deleted)") {
+                Some(path) => format!("(deleted) {path}"),
+                None => exe,
+            }
+        }
+        Err(procfs::ProcError::NotFound(_)) if process.is_alive() => format!("(deleted) {comm}"),
+        Err(procfs::ProcError::NotFound(_)) => format!("(vanished) {comm}"),
+        Err(e) => {
+            debug!("Can't read exe of pid {}: {e:?}", process.pid);
+            format!("(unreadable) {comm}")
+        }
+    }
+}
+
+/// Key of a process, and the name of its group
+type InfoKey<K> = fn(&ProcessInfo) -> (K, String);
+
+/// Group processes by a key read from their `ProcessInfo`, so it also works on snapshots
+///
+/// The accessor returns the key and the name of the group; the name is taken from the first
+/// process of each group
+pub struct ProcessSplitterByInfo<K, F = InfoKey<K>> {
+    name: String,
+    key: F,
+    groups: BTreeMap<K, ProcessGroupInfo>,
 }
 
-impl ProcessSplitterUid {
-    pub fn new() -> Self {
+impl<K, F> ProcessSplitterByInfo<K, F>
+where
+    F: Fn(&ProcessInfo) -> (K, String),
+{
+    pub fn new(name: &str, key: F) -> Self {
         Self {
+            name: name.to_string(),
+            key,
             groups: BTreeMap::new(),
         }
     }
 }
-impl<'a> ProcessSplitter<'a> for ProcessSplitterUid {
-    type GroupIter<'b: 'a> = std::collections::btree_map::Values<'a, u32, ProcessGroupInfo>;
+impl ProcessSplitterByInfo<u32> {
+    /// Split by real user, named after the user when it exists
+    pub fn uid() -> Self {
+        Self::new("UID", |process_info| {
+            let uid = process_info.uid;
+            let name = match uzers::get_user_by_uid(uid) {
+                Some(user) => user.name().to_string_lossy().to_string(),
+                None => format!("{uid}"),
+            };
+            (uid, name)
+        })
+    }
+    /// Split by real primary group, e.g. when workloads share a group rather than a user
+    pub fn gid() -> Self {
+        Self::new("GID", |process_info| {
+            let gid = process_info.gid;
+            let name = match uzers::get_group_by_gid(gid) {
+                Some(group) => group.name().to_string_lossy().to_string(),
+                None => format!("{gid}"),
+            };
+            (gid, name)
+        })
+    }
+}
+impl ProcessSplitterByInfo<String> {
+    pub fn comm() -> Self {
+        Self::new("comm", |process_info| {
+            let comm = process_info.comm.clone();
+            let name = format!("comm {comm}");
+            (comm, name)
+        })
+    }
+    /// Split by executable path. A process loaded from a snapshot can't be read, and is grouped
+    /// by comm instead
+    pub fn exe() -> Self {
+        Self::new("exe", |process_info| {
+            let exe = match &process_info.process {
+                Some(process) => exe_label(process, &process_info.comm),
+                // loaded from a snapshot
+                None => format!("(unknown) {}", process_info.comm),
+            };
+            let name = format!("exe {exe}");
+            (exe, name)
+        })
+    }
+}
+impl<'a, K, F> ProcessSplitter<'a> for ProcessSplitterByInfo<K, F>
+where
+    K: Ord + std::hash::Hash + Send + 'a,
+    F: Fn(&ProcessInfo) -> (K, String) + 'a,
+{
+    type GroupIter<'b: 'a> = std::collections::btree_map::Values<'a, K, ProcessGroupInfo>;
 
     fn name(&self) -> String {
-        "UID".to_string()
+        self.name.clone()
     }
     fn __split(
         &mut self,
         _tree: &ProcessTree,
         shms_metadata: &ShmsMetadata,
-        mut processes: Vec<ProcessInfo>,
+        processes: Vec<ProcessInfo>,
     ) {
-        let uids: HashSet<u32> = processes.iter().map(|p| p.uid).collect();
-
-        for uid in uids {
-            let username = uzers::get_user_by_uid(uid);
-            let username = match username {
-                Some(username) => username.name().to_string_lossy().to_string(),
-                None => format!("{uid}"),
-            };
-            let processes_info: Vec<ProcessInfo> = processes.extract_if(|p| p.uid == uid).collect();
-            let group_info = get_processes_group_info(processes_info, &username, shms_metadata);
-            self.groups.insert(uid, group_info);
+        let mut processes_by_key: HashMap<K, (String, Vec<ProcessInfo>)> = HashMap::new();
+        for process_info in processes {
+            let (key, name) = (self.key)(&process_info);
+            processes_by_key
+                .entry(key)
+                .or_insert_with(|| (name, Vec::new()))
+                .1
+                .push(process_info);
         }
+
+        // groups are independent, compute them in parallel
+        self.groups = processes_by_key
+            .into_par_iter()
+            .map(|(key, (name, processes_info))| {
+                let group_info = get_processes_group_info(processes_info, &name, shms_metadata);
+                (key, group_info)
+            })
+            .collect();
     }
     fn iter_groups<'x>(&'a self) -> Self::GroupIter<'a> {
         self.groups.values()
@@ -342,3 +1484,959 @@ impl<'a> ProcessSplitter<'a> for ProcessSplitterUid {
             .collect()
     }
 }
+
+/// Bucket processes by RSS, to get a histogram of processes sizes
+pub struct ProcessSplitterByMemoryThreshold {
+    /// Sorted upper bounds of the buckets, in bytes. The last bucket has no upper bound
+    thresholds: Vec<u64>,
+    groups: BTreeMap<usize, ProcessGroupInfo>,
+}
+
+impl ProcessSplitterByMemoryThreshold {
+    pub const DEFAULT_THRESHOLDS: [u64; 3] = [10 << 20, 100 << 20, 1 << 30];
+
+    pub fn new(thresholds: &[u64]) -> Self {
+        let mut thresholds = thresholds.to_vec();
+        thresholds.sort_unstable();
+        thresholds.dedup();
+        Self {
+            thresholds,
+            groups: BTreeMap::new(),
+        }
+    }
+
+    /// Labels don't depend on `--unit`
+    fn bucket_name(&self, bucket: usize) -> String {
+        let format = |size: u64| humansize::format_size(size, humansize::BINARY);
+        let low = bucket.checked_sub(1).map(|idx| self.thresholds[idx]);
+        let high = self.thresholds.get(bucket).copied();
+        match (low, high) {
+            (None, Some(high)) => format!("rss < {}", format(high)),
+            (Some(low), Some(high)) => format!("rss {} - {}", format(low), format(high)),
+            (Some(low), None) => format!("rss > {}", format(low)),
+            (None, None) => "all".to_string(),
+        }
+    }
+}
+impl<'a> ProcessSplitter<'a> for ProcessSplitterByMemoryThreshold {
+    type GroupIter<'b: 'a> = std::collections::btree_map::Values<'a, usize, ProcessGroupInfo>;
+
+    fn name(&self) -> String {
+        "rss".to_string()
+    }
+    fn __split(
+        &mut self,
+        _tree: &ProcessTree,
+        shms_metadata: &ShmsMetadata,
+        processes: Vec<ProcessInfo>,
+    ) {
+        let mut processes_by_bucket: HashMap<usize, Vec<ProcessInfo>> = HashMap::new();
+        for process_info in processes {
+            let bucket = self
+                .thresholds
+                .partition_point(|threshold| *threshold <= process_info.rss);
+            processes_by_bucket
+                .entry(bucket)
+                .or_default()
+                .push(process_info);
+        }
+
+        // groups are independent, compute them in parallel
+        self.groups = processes_by_bucket
+            .into_par_iter()
+            .map(|(bucket, processes_info)| {
+                let name = self.bucket_name(bucket);
+                let group_info = get_processes_group_info(processes_info, &name, shms_metadata);
+                (bucket, group_info)
+            })
+            .collect();
+    }
+    fn iter_groups<'x>(&'a self) -> Self::GroupIter<'a> {
+        self.groups.values()
+    }
+    fn collect_processes(self) -> Vec<ProcessInfo> {
+        self.groups
+            .into_values()
+            .flat_map(|group| group.processes_info)
+            .collect()
+    }
+}
+
+pub struct ProcessSplitterBySessionId {
+    groups: BTreeMap<Option<i32>, ProcessGroupInfo>,
+}
+
+impl ProcessSplitterBySessionId {
+    pub fn new() -> Self {
+        Self {
+            groups: BTreeMap::new(),
+        }
+    }
+}
+impl<'a> ProcessSplitter<'a> for ProcessSplitterBySessionId {
+    type GroupIter<'b: 'a> = std::collections::btree_map::Values<'a, Option<i32>, ProcessGroupInfo>;
+
+    fn name(&self) -> String {
+        "session".to_string()
+    }
+    fn __split(
+        &mut self,
+        _tree: &ProcessTree,
+        shms_metadata: &ShmsMetadata,
+        processes: Vec<ProcessInfo>,
+    ) {
+        let mut processes_by_session: HashMap<Option<i32>, Vec<ProcessInfo>> = HashMap::new();
+        for process_info in processes {
+            let session = process_info
+                .process
+                .as_ref()
+                .and_then(|process| retry_proc(|| process.stat()).ok())
+                .map(|stat| stat.session);
+            processes_by_session
+                .entry(session)
+                .or_default()
+                .push(process_info);
+        }
+
+        // groups are independent, compute them in parallel
+        self.groups = processes_by_session
+            .into_par_iter()
+            .map(|(session, processes_info)| {
+                let name = match session {
+                    Some(session) => {
+                        // the session leader may have exited, or not be scanned
+                        let leader = processes_info
+                            .iter()
+                            .find(|process_info| process_info.pid == session)
+                            .map(|process_info| process_info.comm.clone())
+                            .or_else(|| Some(crate::process(session).ok()?.stat().ok()?.comm));
+                        match leader {
+                            Some(comm) => format!("session {session} ({comm})"),
+                            None => format!("session {session}"),
+                        }
+                    }
+                    None => "Unknown session".to_string(),
+                };
+                let group_info = get_processes_group_info(processes_info, &name, shms_metadata);
+                (session, group_info)
+            })
+            .collect();
+    }
+    fn iter_groups<'x>(&'a self) -> Self::GroupIter<'a> {
+        self.groups.values()
+    }
+    fn collect_processes(self) -> Vec<ProcessInfo> {
+        self.groups
+            .into_values()
+            .flat_map(|group| group.processes_info)
+            .collect()
+    }
+}
+
+/// Group processes by the value returned by a closure, for grouping logic that has no dedicated splitter
+///
+/// e.g. `ProcessSplitterByFn::new("nice", |p| p.stat().map(|stat| stat.nice).unwrap_or(0))`
+/// Processes loaded from a snapshot can't be read, they are in the "Unknown" group
+pub struct ProcessSplitterByFn<K, F> {
+    name: String,
+    key: F,
+    groups: BTreeMap<Option<K>, ProcessGroupInfo>,
+}
+
+impl<K, F> ProcessSplitterByFn<K, F>
+where
+    F: Fn(&Process) -> K,
+{
+    pub fn new(name: &str, key: F) -> Self {
+        Self {
+            name: name.to_string(),
+            key,
+            groups: BTreeMap::new(),
+        }
+    }
+}
+impl<'a, K, F> ProcessSplitter<'a> for ProcessSplitterByFn<K, F>
+where
+    K: Ord + std::hash::Hash + std::fmt::Display + Send + 'a,
+    F: Fn(&Process) -> K + 'a,
+{
+    type GroupIter<'b: 'a> = std::collections::btree_map::Values<'a, Option<K>, ProcessGroupInfo>;
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn __split(
+        &mut self,
+        _tree: &ProcessTree,
+        shms_metadata: &ShmsMetadata,
+        processes: Vec<ProcessInfo>,
+    ) {
+        let mut processes_by_key: HashMap<Option<K>, Vec<ProcessInfo>> = HashMap::new();
+        for process_info in processes {
+            let key = process_info.process.as_ref().map(&self.key);
+            processes_by_key.entry(key).or_default().push(process_info);
+        }
+
+        // groups are independent, compute them in parallel
+        self.groups = processes_by_key
+            .into_par_iter()
+            .map(|(key, processes_info)| {
+                let name = match &key {
+                    Some(key) => key.to_string(),
+                    None => "Unknown".to_string(),
+                };
+                let group_info = get_processes_group_info(processes_info, &name, shms_metadata);
+                (key, group_info)
+            })
+            .collect();
+    }
+    fn iter_groups<'x>(&'a self) -> Self::GroupIter<'a> {
+        self.groups.values()
+    }
+    fn collect_processes(self) -> Vec<ProcessInfo> {
+        self.groups
+            .into_values()
+            .flat_map(|group| group.processes_info)
+            .collect()
+    }
+}
+
+/// Return the cgroup v2 path of a process (unified hierarchy, `0::` line in /proc/<pid>/cgroup)
+fn get_cgroup_v2_path(process: &Process) -> Option<String> {
+    retry_proc(|| process.cgroups())
+        .ok()?
+        .into_iter()
+        .find(|cgroup| cgroup.hierarchy == 0 && cgroup.controllers.is_empty())
+        .map(|cgroup| cgroup.pathname)
+}
+
+pub struct ProcessSplitterCgroup {
+    groups: BTreeMap<Option<String>, ProcessGroupInfo>,
+}
+
+impl ProcessSplitterCgroup {
+    pub fn new() -> Self {
+        Self {
+            groups: BTreeMap::new(),
+        }
+    }
+}
+impl<'a> ProcessSplitter<'a> for ProcessSplitterCgroup {
+    type GroupIter<'b: 'a> =
+        std::collections::btree_map::Values<'a, Option<String>, ProcessGroupInfo>;
+
+    fn name(&self) -> String {
+        "cgroup v2".to_string()
+    }
+    /// Same as the default, plus memory charged to each cgroup by the kernel
+    fn display(
+        &'a self,
+        shm_metadata: &ShmsMetadata,
+        physical_pages: &PhysicalPages,
+        kernel_sizes: Option<(u64, u64)>,
+        view: ReportsView,
+    ) {
+        let reports = self.reports(shm_metadata, physical_pages, kernel_sizes, view);
+        display_reports(&self.name(), &reports);
+
+        #[derive(Tabled)]
+        struct CgroupMemoryDisplayRow {
+            group_name: String,
+            #[tabled(display_with = "format_size")]
+            mem_rss: u64,
+            #[tabled(rename = "memory.current", display_with = "format_optional_size")]
+            memory_current: Option<u64>,
+            #[tabled(rename = "memory.peak", display_with = "format_optional_size")]
+            memory_peak: Option<u64>,
+            #[tabled(display_with = "format_size")]
+            mem_anon: u64,
+            #[tabled(rename = "stat anon", display_with = "format_optional_size")]
+            stat_anon: Option<u64>,
+            #[tabled(display_with = "format_size")]
+            mem_page_cache: u64,
+            #[tabled(rename = "stat file", display_with = "format_optional_size")]
+            stat_file: Option<u64>,
+            #[tabled(rename = "stat shmem", display_with = "format_optional_size")]
+            stat_shmem: Option<u64>,
+            #[tabled(display_with = "format_size")]
+            swap_rss: u64,
+            #[tabled(rename = "memory.swap.current", display_with = "format_optional_size")]
+            memory_swap_current: Option<u64>,
+        }
+
+        let read_value = |path: &str, file: &str| -> Option<u64> {
+            std::fs::read_to_string(crate::root_path(&format!("/sys/fs/cgroup{path}/{file}")))
+                .ok()?
+                .trim()
+                .parse()
+                .ok()
+        };
+        // "<key> <bytes>" lines
+        let read_stat = |path: &str| -> Option<HashMap<String, u64>> {
+            let content = std::fs::read_to_string(crate::root_path(&format!(
+                "/sys/fs/cgroup{path}/memory.stat"
+            )))
+            .ok()?;
+            Some(
+                content
+                    .lines()
+                    .filter_map(|line| {
+                        let (key, value) = line.split_once(' ')?;
+                        Some((key.to_string(), value.trim().parse().ok()?))
+                    })
+                    .collect(),
+            )
+        };
+        // the kernel also charges page cache and kernel memory, that processes don't map
+        // shmem is charged to the cgroup that faulted it in, even when it's mapped by other groups
+        let rows: Vec<CgroupMemoryDisplayRow> = reports
+            .iter()
+            .filter_map(|report| {
+                let cgroup = self
+                    .groups
+                    .keys()
+                    .flatten()
+                    .find(|path| **path == report.group_name)?;
+                let stat = read_stat(cgroup);
+                let stat_value = |key: &str| stat.as_ref().and_then(|stat| stat.get(key).copied());
+                Some(CgroupMemoryDisplayRow {
+                    group_name: report.group_name.clone(),
+                    mem_rss: report.mem_rss,
+                    memory_current: read_value(cgroup, "memory.current"),
+                    // kernel >= 5.19
+                    memory_peak: read_value(cgroup, "memory.peak"),
+                    mem_anon: report.mem_anon,
+                    stat_anon: stat_value("anon"),
+                    mem_page_cache: report.mem_page_cache,
+                    stat_file: stat_value("file"),
+                    stat_shmem: stat_value("shmem"),
+                    swap_rss: report.swap_rss,
+                    // missing without swap accounting
+                    memory_swap_current: read_value(cgroup, "memory.swap.current"),
+                })
+            })
+            .collect();
+        let mut table = tabled::Table::new(&rows);
+        table.with(tabled::settings::Style::sharp());
+
+        println!("{} (memory charged to cgroups)", self.name());
+        println!("{table}");
+        println!();
+    }
+    fn __split(
+        &mut self,
+        _tree: &ProcessTree,
+        shms_metadata: &ShmsMetadata,
+        processes: Vec<ProcessInfo>,
+    ) {
+        let mut processes_by_cgroup: HashMap<Option<String>, Vec<ProcessInfo>> = HashMap::new();
+        for process_info in processes {
+            let cgroup = process_info.process.as_ref().and_then(get_cgroup_v2_path);
+            processes_by_cgroup
+                .entry(cgroup)
+                .or_default()
+                .push(process_info);
+        }
+
+        // groups are independent, compute them in parallel
+        self.groups = processes_by_cgroup
+            .into_par_iter()
+            .map(|(cgroup, processes_info)| {
+                let name = match &cgroup {
+                    Some(path) => path.clone(),
+                    None => "No cgroup v2".to_string(),
+                };
+                let group_info = get_processes_group_info(processes_info, &name, shms_metadata);
+                (cgroup, group_info)
+            })
+            .collect();
+    }
+    fn iter_groups<'x>(&'a self) -> Self::GroupIter<'a> {
+        self.groups.values()
+    }
+    fn collect_processes(self) -> Vec<ProcessInfo> {
+        self.groups
+            .into_values()
+            .flat_map(|group| group.processes_info)
+            .collect()
+    }
+}
+
+/// Return the cgroup v1 path of a process for a controller (`<hierarchy>:<controllers>:<path>` line in /proc/<pid>/cgroup)
+fn get_cgroup_v1_path(process: &Process, controller: &str) -> Option<String> {
+    retry_proc(|| process.cgroups())
+        .ok()?
+        .into_iter()
+        .find(|cgroup| cgroup.hierarchy != 0 && cgroup.controllers.iter().any(|c| c == controller))
+        .map(|cgroup| cgroup.pathname)
+}
+
+pub struct ProcessSplitterByCgroupV1Controller {
+    controller: String,
+    groups: BTreeMap<Option<String>, ProcessGroupInfo>,
+}
+
+impl ProcessSplitterByCgroupV1Controller {
+    pub fn new(controller: &str) -> Self {
+        Self {
+            controller: controller.to_string(),
+            groups: BTreeMap::new(),
+        }
+    }
+}
+impl<'a> ProcessSplitter<'a> for ProcessSplitterByCgroupV1Controller {
+    type GroupIter<'b: 'a> =
+        std::collections::btree_map::Values<'a, Option<String>, ProcessGroupInfo>;
+
+    fn name(&self) -> String {
+        format!("cgroup v1 {}", self.controller)
+    }
+    fn __split(
+        &mut self,
+        _tree: &ProcessTree,
+        shms_metadata: &ShmsMetadata,
+        processes: Vec<ProcessInfo>,
+    ) {
+        let mut processes_by_cgroup: HashMap<Option<String>, Vec<ProcessInfo>> = HashMap::new();
+        for process_info in processes {
+            let cgroup = process_info
+                .process
+                .as_ref()
+                .and_then(|process| get_cgroup_v1_path(process, &self.controller));
+            processes_by_cgroup
+                .entry(cgroup)
+                .or_default()
+                .push(process_info);
+        }
+
+        // groups are independent, compute them in parallel
+        self.groups = processes_by_cgroup
+            .into_par_iter()
+            .map(|(cgroup, processes_info)| {
+                let name = match &cgroup {
+                    Some(path) => path.clone(),
+                    None => format!("No {} cgroup", self.controller),
+                };
+                let group_info = get_processes_group_info(processes_info, &name, shms_metadata);
+                (cgroup, group_info)
+            })
+            .collect();
+    }
+    fn iter_groups<'x>(&'a self) -> Self::GroupIter<'a> {
+        self.groups.values()
+    }
+    fn collect_processes(self) -> Vec<ProcessInfo> {
+        self.groups
+            .into_values()
+            .flat_map(|group| group.processes_info)
+            .collect()
+    }
+}
+
+/// Container ID from the cgroup of a process, for docker, containerd, CRI-O, and podman
+/// e.g. `/system.slice/docker-<id>.scope`, `/kubepods/.../cri-containerd-<id>.scope`, `/docker/<id>`
+fn get_container_id(process: &Process) -> Option<String> {
+    const PREFIXES: [&str; 4] = ["docker-", "cri-containerd-", "crio-", "libpod-"];
+
+    retry_proc(|| process.cgroups())
+        .ok()?
+        .into_iter()
+        .find_map(|cgroup| {
+            cgroup.pathname.split('/').find_map(|component| {
+                let component = component.strip_suffix(".scope").unwrap_or(component);
+                let id = PREFIXES
+                    .iter()
+                    .find_map(|prefix| component.strip_prefix(prefix))
+                    .unwrap_or(component);
+                (id.len() == 64 && id.chars().all(|c| c.is_ascii_hexdigit()))
+                    .then(|| id.to_string())
+            })
+        })
+}
+
+/// Image of a container, from the docker API
+fn get_container_image(id: &str) -> Option<String> {
+    use std::io::{Read, Write};
+
+    let mut stream = std::os::unix::net::UnixStream::connect("/var/run/docker.sock").ok()?;
+    stream
+        .set_read_timeout(Some(std::time::Duration::from_secs(1)))
+        .ok()?;
+    write!(
+        stream,
+        "GET /containers/{id}/json HTTP/1.0\r\nHost: docker\r\n\r\n"
+    )
+    .ok()?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+
+    let (headers, body) = response.split_once("\r\n\r\n")?;
+    if !headers.starts_with("HTTP/1.0 200") && !headers.starts_with("HTTP/1.1 200") {
+        return None;
+    }
+    let container: serde_json::Value = serde_json::from_str(body).ok()?;
+    container["Config"]["Image"].as_str().map(|s| s.to_string())
+}
+
+pub struct ProcessSplitterByContainerId {
+    groups: BTreeMap<Option<String>, ProcessGroupInfo>,
+}
+
+impl ProcessSplitterByContainerId {
+    pub fn new() -> Self {
+        Self {
+            groups: BTreeMap::new(),
+        }
+    }
+}
+impl<'a> ProcessSplitter<'a> for ProcessSplitterByContainerId {
+    type GroupIter<'b: 'a> =
+        std::collections::btree_map::Values<'a, Option<String>, ProcessGroupInfo>;
+
+    fn name(&self) -> String {
+        "container".to_string()
+    }
+    fn __split(
+        &mut self,
+        _tree: &ProcessTree,
+        shms_metadata: &ShmsMetadata,
+        processes: Vec<ProcessInfo>,
+    ) {
+        let mut processes_by_container: HashMap<Option<String>, Vec<ProcessInfo>> = HashMap::new();
+        for process_info in processes {
+            let id = process_info.process.as_ref().and_then(get_container_id);
+            processes_by_container
+                .entry(id)
+                .or_default()
+                .push(process_info);
+        }
+
+        // groups are independent, compute them in parallel
+        self.groups = processes_by_container
+            .into_par_iter()
+            .map(|(id, processes_info)| {
+                let name = match &id {
+                    Some(id) => {
+                        let short_id = &id[..12];
+                        match get_container_image(id) {
+                            Some(image) => format!("{image} ({short_id})"),
+                            None => short_id.to_string(),
+                        }
+                    }
+                    None => "No container".to_string(),
+                };
+                let group_info = get_processes_group_info(processes_info, &name, shms_metadata);
+                (id, group_info)
+            })
+            .collect();
+    }
+    fn iter_groups<'x>(&'a self) -> Self::GroupIter<'a> {
+        self.groups.values()
+    }
+    fn collect_processes(self) -> Vec<ProcessInfo> {
+        self.groups
+            .into_values()
+            .flat_map(|group| group.processes_info)
+            .collect()
+    }
+}
+
+/// Single group with all processes, for global statistics
+pub struct ProcessSplitterAll {
+    groups: Vec<ProcessGroupInfo>,
+}
+
+impl ProcessSplitterAll {
+    pub fn new() -> Self {
+        Self { groups: Vec::new() }
+    }
+}
+impl<'a> ProcessSplitter<'a> for ProcessSplitterAll {
+    type GroupIter<'b: 'a> = std::slice::Iter<'a, ProcessGroupInfo>;
+
+    fn name(&self) -> String {
+        "Global".to_string()
+    }
+    fn __split(
+        &mut self,
+        _tree: &ProcessTree,
+        shms_metadata: &ShmsMetadata,
+        processes: Vec<ProcessInfo>,
+    ) {
+        let group_info = get_processes_group_info(processes, "All processes", shms_metadata);
+        self.groups = vec![group_info];
+    }
+    fn iter_groups<'x>(&'a self) -> Self::GroupIter<'a> {
+        self.groups.iter()
+    }
+    fn collect_processes(self) -> Vec<ProcessInfo> {
+        self.groups
+            .into_iter()
+            .flat_map(|group| group.processes_info)
+            .collect()
+    }
+}
+
+/// Split by namespace (pid, mnt, net...), using the inode of /proc/<pid>/ns/<namespace>
+pub struct ProcessSplitterNamespace {
+    namespace: String,
+    groups: BTreeMap<Option<u64>, ProcessGroupInfo>,
+}
+
+impl ProcessSplitterNamespace {
+    pub fn new(namespace: &str) -> Self {
+        Self {
+            namespace: namespace.to_string(),
+            groups: BTreeMap::new(),
+        }
+    }
+}
+impl<'a> ProcessSplitter<'a> for ProcessSplitterNamespace {
+    type GroupIter<'b: 'a> = std::collections::btree_map::Values<'a, Option<u64>, ProcessGroupInfo>;
+
+    fn name(&self) -> String {
+        format!("{} namespace", self.namespace)
+    }
+    fn __split(
+        &mut self,
+        _tree: &ProcessTree,
+        shms_metadata: &ShmsMetadata,
+        processes: Vec<ProcessInfo>,
+    ) {
+        use std::os::unix::fs::MetadataExt;
+
+        let mut processes_by_namespace: HashMap<Option<u64>, Vec<ProcessInfo>> = HashMap::new();
+        for process_info in processes {
+            // a loaded snapshot has no access to the processes
+            let inode = process_info.process.as_ref().and_then(|process| {
+                let path =
+                    crate::root_path(&format!("/proc/{}/ns/{}", process.pid, self.namespace));
+                std::fs::metadata(path).ok().map(|metadata| metadata.ino())
+            });
+            processes_by_namespace
+                .entry(inode)
+                .or_default()
+                .push(process_info);
+        }
+
+        // groups are independent, compute them in parallel
+        self.groups = processes_by_namespace
+            .into_par_iter()
+            .map(|(inode, processes_info)| {
+                // same format as the /proc/<pid>/ns/<namespace> symlink
+                let name = match inode {
+                    Some(inode) => format!("{}:[{inode}]", self.namespace),
+                    None => format!("{}:[?]", self.namespace),
+                };
+                let group_info = get_processes_group_info(processes_info, &name, shms_metadata);
+                (inode, group_info)
+            })
+            .collect();
+    }
+    fn iter_groups<'x>(&'a self) -> Self::GroupIter<'a> {
+        self.groups.values()
+    }
+    fn collect_processes(self) -> Vec<ProcessInfo> {
+        self.groups
+            .into_values()
+            .flat_map(|group| group.processes_info)
+            .collect()
+    }
+}
+
+/// Split by top-level ancestor: the last ancestor before reaching one of the root pids
+pub struct ProcessSplitterAncestor {
+    roots: HashSet<i32>,
+    groups: BTreeMap<i32, ProcessGroupInfo>,
+}
+
+impl ProcessSplitterAncestor {
+    /// Guard against cycles caused by pid reuse
+    const MAX_DEPTH: usize = 1024;
+
+    /// pid 0 (parent of init and kthreadd) is always a root
+    pub fn new(roots: &[i32]) -> Self {
+        let mut roots: HashSet<i32> = roots.iter().copied().collect();
+        roots.insert(0);
+        Self {
+            roots,
+            groups: BTreeMap::new(),
+        }
+    }
+
+    /// The walk stops at the init of a PID namespace: the ancestors outside of it are unrelated
+    fn top_ancestor(&self, tree: &ProcessTree, parents: &HashMap<i32, i32>, pid: i32) -> i32 {
+        let mut visited = HashSet::new();
+        let mut current = pid;
+        for _ in 0..Self::MAX_DEPTH {
+            if !visited.insert(current) {
+                break;
+            }
+            match parents.get(&current) {
+                Some(ppid)
+                    if !self.roots.contains(ppid)
+                        && !tree.crosses_pid_namespace(current, *ppid) =>
+                {
+                    current = *ppid
+                }
+                _ => break,
+            }
+        }
+        current
+    }
+}
+impl<'a> ProcessSplitter<'a> for ProcessSplitterAncestor {
+    type GroupIter<'b: 'a> = std::collections::btree_map::Values<'a, i32, ProcessGroupInfo>;
+
+    fn name(&self) -> String {
+        "top-level ancestor".to_string()
+    }
+    fn __split(
+        &mut self,
+        tree: &ProcessTree,
+        shms_metadata: &ShmsMetadata,
+        processes: Vec<ProcessInfo>,
+    ) {
+        let parents = tree.parents();
+        let comms: HashMap<i32, String> =
+            processes.iter().map(|p| (p.pid, p.comm.clone())).collect();
+
+        let mut processes_by_ancestor: HashMap<i32, Vec<ProcessInfo>> = HashMap::new();
+        for process_info in processes {
+            let ancestor = self.top_ancestor(tree, &parents, process_info.pid);
+            processes_by_ancestor
+                .entry(ancestor)
+                .or_default()
+                .push(process_info);
+        }
+
+        // groups are independent, compute them in parallel
+        self.groups = processes_by_ancestor
+            .into_par_iter()
+            .map(|(ancestor, processes_info)| {
+                // ancestor may not be scanned (kernel thread, filtered, vanished)
+                let comm = comms.get(&ancestor).cloned().unwrap_or_else(|| {
+                    crate::process(ancestor)
+                        .and_then(|p| p.stat())
+                        .map(|stat| stat.comm)
+                        .unwrap_or_else(|_| "?".to_string())
+                });
+                let name = if tree.in_other_pid_namespace(ancestor) {
+                    format!("{comm} ({ancestor}, other pid namespace)")
+                } else {
+                    format!("{comm} ({ancestor})")
+                };
+                let group_info = get_processes_group_info(processes_info, &name, shms_metadata);
+                (ancestor, group_info)
+            })
+            .collect();
+    }
+    fn iter_groups<'x>(&'a self) -> Self::GroupIter<'a> {
+        self.groups.values()
+    }
+    fn collect_processes(self) -> Vec<ProcessInfo> {
+        self.groups
+            .into_values()
+            .flat_map(|group| group.processes_info)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 6 processes of 3 uids, ORACLE_SID groups them differently
+    fn processes_of_3_uids() -> Vec<ProcessInfo> {
+        vec![
+            ProcessInfo::test(1)
+                .with_uid(1000)
+                .with_env("ORACLE_SID", "A"),
+            ProcessInfo::test(2)
+                .with_uid(1000)
+                .with_env("ORACLE_SID", "A"),
+            ProcessInfo::test(3)
+                .with_uid(1000)
+                .with_env("ORACLE_SID", "B"),
+            ProcessInfo::test(4)
+                .with_uid(1001)
+                .with_env("ORACLE_SID", "B"),
+            ProcessInfo::test(5).with_uid(1002),
+            ProcessInfo::test(6).with_uid(1002),
+        ]
+    }
+
+    /// Sorted pids of each group, groups sorted by their smallest pid
+    fn members<'a, S: ProcessSplitter<'a>>(splitter: &'a S) -> Vec<Vec<i32>> {
+        let mut members: Vec<Vec<i32>> = splitter
+            .iter_groups()
+            .map(|group| {
+                group
+                    .processes_info
+                    .iter()
+                    .map(|p| p.pid)
+                    .sorted()
+                    .collect()
+            })
+            .collect();
+        members.sort();
+        members
+    }
+
+    fn view() -> ReportsView {
+        ReportsView {
+            key: SortKey::Name,
+            ascending: true,
+            min_rss: 0,
+        }
+    }
+
+    #[test]
+    fn uss_counts_single_group_pages_once() {
+        // pfn 3 is shared inside group 1000, pfn 4 between groups 1000 and 1001
+        let processes = vec![
+            ProcessInfo::test(1).with_uid(1000).with_pfns(&[1, 2, 3]),
+            ProcessInfo::test(2).with_uid(1000).with_pfns(&[3, 4]),
+            ProcessInfo::test(3).with_uid(1001).with_pfns(&[4, 5]),
+            ProcessInfo::test(4).with_uid(1002).with_pfns(&[6]),
+        ];
+        let tree = ProcessTree::new(&[]);
+        let shms_metadata = ShmsMetadata::default();
+        let physical_pages = PhysicalPages::default();
+
+        let mut splitter = ProcessSplitterByInfo::uid();
+        splitter.split(&tree, &shms_metadata, processes);
+        let reports = splitter.reports(&shms_metadata, &physical_pages, None, view());
+
+        // group names depend on the users of the host, identify groups by their pids
+        let page_size = procfs::page_size();
+        let uss: Vec<(Vec<i32>, u64)> = reports
+            .iter()
+            .map(|report| {
+                (
+                    report.pids.iter().copied().sorted().collect(),
+                    report.mem_uss / page_size,
+                )
+            })
+            .sorted()
+            .collect();
+        assert_eq!(uss, [(vec![1, 2], 3), (vec![3], 1), (vec![4], 1)]);
+        // single-group pfns: 1, 2, 3, 5, 6
+        assert_eq!(
+            reports.iter().map(|report| report.mem_uss).sum::<u64>(),
+            5 * page_size
+        );
+    }
+
+    #[test]
+    fn split_by_uid() {
+        let tree = ProcessTree::new(&[]);
+        let shms_metadata = ShmsMetadata::default();
+
+        let mut splitter = ProcessSplitterByInfo::uid();
+        splitter.split(&tree, &shms_metadata, processes_of_3_uids());
+        assert_eq!(members(&splitter), [vec![1, 2, 3], vec![4], vec![5, 6]]);
+
+        let pids: Vec<i32> = splitter
+            .collect_processes()
+            .iter()
+            .map(|p| p.pid)
+            .sorted()
+            .collect();
+        assert_eq!(pids, [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn split_by_env_variable() {
+        let tree = ProcessTree::new(&[]);
+        let shms_metadata = ShmsMetadata::default();
+
+        let mut splitter = ProcessSplitterEnvVariable::new(["ORACLE_SID"]);
+        splitter.split(&tree, &shms_metadata, processes_of_3_uids());
+        assert_eq!(members(&splitter), [vec![1, 2], vec![3, 4], vec![5, 6]]);
+        let names: Vec<&str> = splitter
+            .iter_groups()
+            .map(|group| group.name.as_str())
+            .sorted()
+            .collect();
+        assert_eq!(names, ["None", "Some(\"A\")", "Some(\"B\")"]);
+
+        let pids: Vec<i32> = splitter
+            .collect_processes()
+            .iter()
+            .map(|p| p.pid)
+            .sorted()
+            .collect();
+        assert_eq!(pids, [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn info_splitters_names_and_order() {
+        let tree = ProcessTree::new(&[]);
+        let shms_metadata = ShmsMetadata::default();
+        // ids without a user or group, so that names don't depend on the host
+        let processes = || -> Vec<ProcessInfo> {
+            [
+                (1, 4_000_000_001, "b"),
+                (2, 4_000_000_000, "a"),
+                (3, 4_000_000_001, "a"),
+            ]
+            .into_iter()
+            .map(|(pid, id, comm)| {
+                ProcessInfo::test(pid)
+                    .with_uid(id)
+                    .with_gid(id)
+                    .with_comm(comm)
+            })
+            .collect()
+        };
+        // groups are sorted by key
+        fn names<'a, S: ProcessSplitter<'a>>(splitter: &'a S) -> Vec<String> {
+            splitter
+                .iter_groups()
+                .map(|group| group.name.clone())
+                .collect()
+        }
+
+        let mut uid = ProcessSplitterByInfo::uid();
+        uid.split(&tree, &shms_metadata, processes());
+        assert_eq!(uid.name(), "UID");
+        assert_eq!(names(&uid), ["4000000000", "4000000001"]);
+
+        let mut gid = ProcessSplitterByInfo::gid();
+        gid.split(&tree, &shms_metadata, processes());
+        assert_eq!(gid.name(), "GID");
+        assert_eq!(names(&gid), ["4000000000", "4000000001"]);
+
+        let mut comm = ProcessSplitterByInfo::comm();
+        comm.split(&tree, &shms_metadata, processes());
+        assert_eq!(comm.name(), "comm");
+        assert_eq!(names(&comm), ["comm a", "comm b"]);
+
+        // processes loaded from a snapshot
+        let mut exe = ProcessSplitterByInfo::exe();
+        exe.split(&tree, &shms_metadata, processes());
+        assert_eq!(exe.name(), "exe");
+        assert_eq!(names(&exe), ["exe (unknown) a", "exe (unknown) b"]);
+    }
+
+    #[test]
+    fn exe_of_deleted_binary() {
+        let dir = std::env::temp_dir().join(format!("snap-exe-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let binary = dir.join("sleep");
+        std::fs::copy("/bin/sleep", &binary).unwrap();
+        let mut child = std::process::Command::new(&binary)
+            .arg("10")
+            .spawn()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let process = Process::new(child.id() as i32).unwrap();
+        let label = exe_label(&process, "sleep");
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        assert_eq!(label, format!("(deleted) {}", binary.display()));
+    }
+}
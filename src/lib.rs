@@ -2,6 +2,13 @@
 #![cfg_attr(target_os = "linux", feature(setgroups))]
 #![allow(non_snake_case)]
 
+//! Building blocks of the memory tools
+//!
+//! - [`get_process_info`]: scan the memory of a single process
+//! - [`get_processes_group_info`]: merge the memory of multiple processes
+//! - [`groups::ProcessSplitter`]: split processes into groups, and report memory usage of each group
+//! - [`filters`]: filters to select processes
+
 // https://biriukov.dev/docs/page-cache/4-page-cache-eviction-and-page-reclaim/
 // cat /proc/$(pidof cat)/smaps_rollup
 // cat /proc/$(pidof cat)/status
@@ -15,8 +22,8 @@ use itertools::Itertools;
 use procfs::{
     page_size,
     process::{MMapPath, Process},
-    process::{MemoryMap, PageInfo},
-    Shm, WithCurrentSystemInfo,
+    process::{MemoryMap, MemoryPageFlags, PageInfo},
+    FromRead, Shm, WithCurrentSystemInfo,
 };
 #[cfg(unix)]
 use std::os::unix::process::CommandExt;
@@ -25,10 +32,12 @@ use procfs_core::{
     process::Pfn, ExplicitSystemInfo, PhysicalMemoryMap, PhysicalPageFlags, WithSystemInfo,
 };
 
-use rayon::prelude::ParallelExtend;
+#[cfg(unix)]
+use crate::error::ProcStatsError;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap, HashSet},
     ffi::OsStr,
     fmt::{Debug, Display},
     hash::BuildHasherDefault,
@@ -36,11 +45,14 @@ use std::{
     str::FromStr,
 };
 
-use log::{info, warn};
+use log::{debug, info, warn};
 
 use oracle::{Connector, Privilege};
 use std::ffi::OsString;
+use std::path::PathBuf;
 
+#[cfg(unix)]
+pub mod error;
 #[cfg(unix)]
 pub mod filters;
 #[cfg(unix)]
@@ -48,8 +60,46 @@ pub mod groups;
 #[cfg(unix)]
 pub mod process_tree;
 #[cfg(unix)]
+pub mod snapshot;
+#[cfg(unix)]
 pub mod tmpfs;
 
+static ROOT: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+
+/// Read /proc and /sys under `root` instead of /, e.g. a copy taken on another machine
+/// Only the first call has an effect
+pub fn set_root(root: PathBuf) {
+    if ROOT.set(root).is_err() {
+        warn!("Root is already set");
+    }
+}
+
+/// Whether /proc and /sys are the ones of the running kernel, see `set_root`
+/// Pages, idle pages and shm can only be read from the running kernel
+pub fn live_root() -> bool {
+    ROOT.get().is_none()
+}
+
+/// Absolute path of a file of /proc or /sys, under the root from `set_root`
+pub fn root_path(path: &str) -> PathBuf {
+    match ROOT.get() {
+        Some(root) => root.join(path.trim_start_matches('/')),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Same as `procfs::process::all_processes`, under the root from `set_root`
+#[cfg(unix)]
+pub fn all_processes() -> procfs::ProcResult<procfs::process::ProcessesIter> {
+    procfs::process::all_processes_with_root(root_path("/proc"))
+}
+
+/// Same as `Process::new`, under the root from `set_root`
+#[cfg(unix)]
+pub fn process(pid: i32) -> procfs::ProcResult<Process> {
+    Process::new_with_root(root_path(&format!("/proc/{pid}")))
+}
+
 /// Convert pfn to index into non-contiguous memory mappings
 pub fn pfn_to_index(iomem: &[PhysicalMemoryMap], page_size: u64, pfn: Pfn) -> Option<u64> {
     if pfn.0 == 0 {
@@ -289,6 +339,7 @@ pub fn shm2pfns(
     }
 
     let mut total_pages = 0;
+    // PFNs of base pages, huge pages (2MiB, or 1GiB gigantic pages) are made of contiguous ones
     let mut huge_pages = 0;
     for pfn in &pfns {
         let flags = match all_physical_pages.get(pfn) {
@@ -302,7 +353,8 @@ pub fn shm2pfns(
         }
     }
     let pages_4k = total_pages - huge_pages;
-    let pages_2M = huge_pages / 512;
+    // in bytes, as the size of huge pages depends on the hugetlbfs mount or SHM_HUGE_* flags
+    let huge_size = huge_pages * page_size() as usize;
 
     // detach shm
     unsafe {
@@ -314,7 +366,7 @@ pub fn shm2pfns(
     }
 
     if must_read {
-        Ok(Some((pfns, swap_pages, pages_4k, pages_2M)))
+        Ok(Some((pfns, swap_pages, pages_4k, huge_size)))
     } else {
         Ok(None)
     }
@@ -356,11 +408,11 @@ pub fn get_kernel_datastructure_size(
 pub fn get_memory_maps_for_process(
     process: &Process,
     optimize_shm: bool,
-) -> Result<Vec<(MemoryMap, Vec<PageInfo>)>, Box<dyn std::error::Error>> {
+) -> Result<Vec<(MemoryMap, Vec<PageInfo>)>, ProcStatsError> {
     let page_size = procfs::page_size();
 
-    let mut pagemap = process.pagemap()?;
-    let memmap = process.maps()?;
+    let mut pagemap = read_proc(process.pid, || process.pagemap())?;
+    let memmap = read_proc(process.pid, || process.maps())?;
 
     let result = memmap
         .iter()
@@ -379,7 +431,13 @@ pub fn get_memory_maps_for_process(
 
             let pages = match pagemap.get_range_info(index_start..index_end) {
                 Ok(x) => x,
-                Err(_) => return None,
+                Err(e) => {
+                    warn!(
+                        "Can't read pagemap for pid {} mapping {:#x}-{:#x}: {e:?}",
+                        process.pid, memory_map.address.0, memory_map.address.1
+                    );
+                    return None;
+                }
             };
 
             Some((memory_map.clone(), pages))
@@ -389,6 +447,44 @@ pub fn get_memory_maps_for_process(
     Ok(result)
 }
 
+/// Parse /proc/<pid>/smaps_rollup (kernel >= 4.14)
+/// Return fields (Rss, Pss, Swap...) in bytes
+#[cfg(unix)]
+pub fn get_smaps_rollup(process: &Process) -> Result<HashMap<String, u64>, ProcStatsError> {
+    let path = root_path(&format!("/proc/{}/smaps_rollup", process.pid));
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        // the process is still there, but not the file
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && process.is_alive() => {
+            return Err(ProcStatsError::KernelFeatureUnavailable(
+                "/proc/<pid>/smaps_rollup (kernel >= 4.14)".to_string(),
+            ))
+        }
+        Err(e) => return Err(ProcStatsError::ProcRead(process.pid, e)),
+    };
+
+    let mut fields = HashMap::new();
+    for line in content.lines() {
+        let mut iter = line.split_whitespace();
+        let (Some(key), Some(value), Some("kB")) = (iter.next(), iter.next(), iter.next()) else {
+            // header, or field without unit
+            continue;
+        };
+        let Some(key) = key.strip_suffix(':') else {
+            continue;
+        };
+        let value: u64 = value.parse().map_err(|e| {
+            ProcStatsError::Parse(format!(
+                "/proc/{}/smaps_rollup field {key}: {e}",
+                process.pid
+            ))
+        })?;
+        fields.insert(key.to_string(), value * 1024);
+    }
+
+    Ok(fields)
+}
+
 #[derive(Serialize, Deserialize, Copy, Clone, Debug)]
 pub enum LargePages {
     True,
@@ -444,7 +540,7 @@ pub fn get_db_info() -> Result<(u64, u64, u64, LargePages), Box<dyn std::error::
 /// For each, return (pid, uid, ORACLE_SID, ORACLE_HOME)
 #[cfg(unix)]
 pub fn find_smons() -> Vec<(i32, u32, OsString, OsString)> {
-    let smons: Vec<Process> = procfs::process::all_processes()
+    let smons: Vec<Process> = all_processes()
         .unwrap()
         .filter_map(|proc| {
             let cmdline = proc.as_ref().ok()?.cmdline().ok()?;
@@ -491,6 +587,7 @@ pub type TheHash = metrohash::MetroHash;
 #[cfg(feature = "fxhash")]
 pub type TheHash = rustc_hash::FxHasher;
 
+/// For each segment: PFNs, swap pages, number of base pages and bytes in huge pages, None if not scanned
 #[cfg(unix)]
 pub type ShmsMetadata = HashMap<
     procfs::Shm,
@@ -498,26 +595,409 @@ pub type ShmsMetadata = HashMap<
     BuildHasherDefault<TheHash>,
 >;
 
-#[derive(Hash, Eq, PartialEq, Debug, Copy, Clone)]
+/// Physical pages metadata, from /proc/kpageflags and /proc/kpagecount
+#[derive(Default, Serialize, Deserialize)]
+pub struct PhysicalPages {
+    /// Flags of each page
+    pub flags: HashMap<Pfn, PhysicalPageFlags>,
+    /// Number of times each page is mapped
+    pub counts: HashMap<Pfn, u64>,
+    /// Sorted NUMA nodes ranges: (start, end excluded, node)
+    pub numa_nodes: Vec<(Pfn, Pfn, u32)>,
+    /// Swap devices by swap type, from /proc/swaps
+    #[serde(default)]
+    pub swap_devices: Vec<String>,
+    /// Whether the IDLE flags were sampled by `sample_idle`, instead of being left by a previous user
+    #[serde(default)]
+    pub idle_sampled: bool,
+}
+
+impl PhysicalPages {
+    /// Whether /proc/kpagecount was read: without counts, private and shared pages are unknown
+    pub fn has_counts(&self) -> bool {
+        !self.counts.is_empty()
+    }
+
+    /// Read flags and counts of all pages in System RAM ranges of /proc/iomem
+    #[cfg(unix)]
+    pub fn read() -> Result<Self, Box<dyn std::error::Error>> {
+        let mut kpageflags = procfs::KPageFlags::new()?;
+        let mut kpagecount = procfs::KPageCount::new()?;
+
+        let mut physical_pages = PhysicalPages::default();
+        for (_indent, map) in procfs::iomem()? {
+            if map.name != "System RAM" {
+                continue;
+            }
+            let (start, end) = map.get_range().get();
+
+            let counts = kpagecount.get_count_in_range(start, end)?;
+            let flags = kpageflags.get_range_info(start, end)?;
+            for ((pfn, flags), count) in (start.0..end.0).map(Pfn).zip(flags).zip(counts) {
+                physical_pages.flags.insert(pfn, flags);
+                physical_pages.counts.insert(pfn, count);
+            }
+        }
+
+        Ok(physical_pages)
+    }
+
+    /// Estimate the working set: mark all pages idle in /sys/kernel/mm/page_idle/bitmap, wait, then set
+    /// the IDLE flag of the pages that were not accessed in the meantime
+    ///
+    /// Requires root and a kernel built with CONFIG_IDLE_PAGE_TRACKING. Only pages on LRU lists can be
+    /// marked idle, others are always active
+    #[cfg(unix)]
+    pub fn sample_idle(
+        &mut self,
+        interval: std::time::Duration,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        use std::os::unix::fs::FileExt;
+
+        let bitmap = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/sys/kernel/mm/page_idle/bitmap")?;
+
+        // the bitmap is read and written by words of 64 pages
+        let ranges: Vec<(u64, u64)> = procfs::iomem()?
+            .into_iter()
+            .filter(|(_indent, map)| map.name == "System RAM")
+            .map(|(_indent, map)| {
+                let (start, end) = map.get_range().get();
+                (start.0 / 64, end.0.div_ceil(64))
+            })
+            .collect();
+
+        for (start, end) in &ranges {
+            let ones = vec![0xff; ((end - start) * 8) as usize];
+            bitmap.write_all_at(&ones, start * 8)?;
+        }
+
+        std::thread::sleep(interval);
+
+        for (start, end) in &ranges {
+            let mut words = vec![0; ((end - start) * 8) as usize];
+            bitmap.read_exact_at(&mut words, start * 8)?;
+            for (idx, word) in words.chunks_exact(8).enumerate() {
+                let word = u64::from_ne_bytes(word.try_into()?);
+                for bit in 0..64 {
+                    let pfn = Pfn((start + idx as u64) * 64 + bit);
+                    if let Some(flags) = self.flags.get_mut(&pfn) {
+                        flags.set(PhysicalPageFlags::IDLE, word & (1 << bit) != 0);
+                    }
+                }
+            }
+        }
+
+        self.idle_sampled = true;
+        Ok(())
+    }
+
+    /// Whether the page is in a System RAM range of /proc/iomem
+    /// If physical pages couldn't be read, ranges are unknown and all pages are assumed to be RAM
+    pub fn is_system_ram(&self, pfn: Pfn) -> bool {
+        self.flags.is_empty() || self.flags.contains_key(&pfn)
+    }
+
+    /// Count pages having any of the flags
+    pub fn count_with_flags<'a, I: IntoIterator<Item = &'a Pfn>>(
+        &self,
+        pfns: I,
+        flags: PhysicalPageFlags,
+    ) -> u64 {
+        pfns.into_iter()
+            .filter(|pfn| {
+                self.flags
+                    .get(*pfn)
+                    .is_some_and(|page_flags| page_flags.intersects(flags))
+            })
+            .count() as u64
+    }
+
+    /// Name of the swap device of a swap type
+    pub fn swap_device(&self, swap_type: u64) -> String {
+        match self.swap_devices.get(swap_type as usize) {
+            Some(device) => device.clone(),
+            None => format!("type {swap_type}"),
+        }
+    }
+
+    /// NUMA node of a page
+    pub fn numa_node(&self, pfn: Pfn) -> Option<u32> {
+        let idx = self
+            .numa_nodes
+            .partition_point(|(start, _end, _node)| start.0 <= pfn.0);
+        let (_start, end, node) = self.numa_nodes.get(idx.checked_sub(1)?)?;
+        if pfn.0 < end.0 {
+            Some(*node)
+        } else {
+            None
+        }
+    }
+}
+
+/// Coalesce pages into sorted runs of contiguous PFNs: (start, number of pages)
+pub fn pfn_runs<'a, I: IntoIterator<Item = &'a Pfn>>(pfns: I) -> Vec<(Pfn, u64)> {
+    let mut sorted: Vec<u64> = pfns
+        .into_iter()
+        .map(|pfn| pfn.0)
+        .filter(|&pfn| pfn != 0)
+        .collect();
+    sorted.sort_unstable();
+    sorted.dedup();
+
+    let mut runs: Vec<(Pfn, u64)> = Vec::new();
+    for pfn in sorted {
+        match runs.last_mut() {
+            Some((start, len)) if start.0 + *len == pfn => *len += 1,
+            _ => runs.push((Pfn(pfn), 1)),
+        }
+    }
+
+    runs
+}
+
+/// Reverse index of the scanned pages: pids of the processes mapping each PFN
+#[cfg(unix)]
+pub fn pfn_mappers(
+    processes_info: &[ProcessInfo],
+) -> HashMap<Pfn, Vec<i32>, BuildHasherDefault<TheHash>> {
+    let mut mappers: HashMap<Pfn, Vec<i32>, BuildHasherDefault<TheHash>> = HashMap::default();
+    for process_info in processes_info {
+        for pfn in process_info.pfns.iter().filter(|pfn| pfn.0 != 0) {
+            mappers.entry(*pfn).or_default().push(process_info.pid);
+        }
+    }
+    mappers
+}
+
+/// Anonymous pages mapped by processes of different users
+#[derive(Debug)]
+pub struct CrossUidPages {
+    pub uids: BTreeSet<u32>,
+    pub pids: BTreeSet<i32>,
+    pub pages: u64,
+}
+
+/// Find anonymous pages mapped by processes of different uids, grouped by set of uids, largest first
+///
+/// File-backed and shm pages are expected to be shared, so only anonymous pages are considered. KSM and zero
+/// pages are shared by design and excluded
+#[cfg(unix)]
+pub fn cross_uid_pages(
+    processes_info: &[ProcessInfo],
+    physical_pages: &PhysicalPages,
+) -> Vec<CrossUidPages> {
+    let mut mappers: HashMap<Pfn, Vec<&ProcessInfo>, BuildHasherDefault<TheHash>> =
+        HashMap::default();
+    for process_info in processes_info {
+        for pfn in process_info.anon_pfns.iter().filter(|pfn| pfn.0 != 0) {
+            mappers.entry(*pfn).or_default().push(process_info);
+        }
+    }
+
+    let mut shared: HashMap<BTreeSet<u32>, CrossUidPages> = HashMap::new();
+    for (pfn, processes) in mappers {
+        let uids: BTreeSet<u32> = processes.iter().map(|p| p.uid).collect();
+        if uids.len() < 2
+            || physical_pages.count_with_flags(
+                [&pfn],
+                PhysicalPageFlags::KSM | PhysicalPageFlags::ZERO_PAGE,
+            ) != 0
+        {
+            continue;
+        }
+        let entry = shared.entry(uids.clone()).or_insert(CrossUidPages {
+            uids,
+            pids: BTreeSet::new(),
+            pages: 0,
+        });
+        entry.pids.extend(processes.iter().map(|p| p.pid));
+        entry.pages += 1;
+    }
+
+    shared
+        .into_values()
+        .sorted_by(|a, b| b.pages.cmp(&a.pages))
+        .collect()
+}
+
+/// Resident pages of a file, as mapped by each process and once for the whole system
+#[derive(Debug)]
+pub struct SharedFile {
+    pub path: PathBuf,
+    pub procs: usize,
+    /// Sum of the resident pages mapped by each process
+    pub mapped_pages: u64,
+    /// Distinct physical pages backing the mappings
+    pub unique_pages: u64,
+}
+
+impl SharedFile {
+    /// Pages that would be used if nothing was shared
+    pub fn saved_pages(&self) -> u64 {
+        self.mapped_pages - self.unique_pages
+    }
+}
+
+/// Compare the naive sum of per-process resident pages of each mapped file with its distinct pages, files saving
+/// the most memory by sharing first
+pub fn shared_files(processes_info: &[ProcessInfo]) -> Vec<SharedFile> {
+    let mut files: HashMap<&PathBuf, (SharedFile, HashSet<Pfn, BuildHasherDefault<TheHash>>)> =
+        HashMap::new();
+    for process_info in processes_info {
+        for (path, pfns) in &process_info.files_pfns {
+            let (file, file_pfns) = files.entry(path).or_insert_with(|| {
+                let file = SharedFile {
+                    path: path.clone(),
+                    procs: 0,
+                    mapped_pages: 0,
+                    unique_pages: 0,
+                };
+                (file, HashSet::default())
+            });
+            file.procs += 1;
+            file.mapped_pages += pfns.len() as u64;
+            file_pfns.extend(pfns);
+        }
+    }
+
+    files
+        .into_values()
+        .map(|(mut file, pfns)| {
+            file.unique_pages = pfns.len() as u64;
+            file
+        })
+        .sorted_by(|a, b| b.saved_pages().cmp(&a.saved_pages()))
+        .collect()
+}
+
+/// Swap devices or files from /proc/swaps, indexed by swap type
+///
+/// The kernel lists active swaps by type, so this is exact unless a swap was disabled while another one with a
+/// higher type is still active
+#[cfg(unix)]
+pub fn get_swap_devices() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let swaps = std::fs::read_to_string(root_path("/proc/swaps"))?;
+    let devices = swaps
+        .lines()
+        // header: Filename Type Size Used Priority
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|filename| filename.to_string())
+        .collect();
+    Ok(devices)
+}
+
+/// Physical memory ranges of NUMA nodes, from memory blocks in /sys/devices/system/node/node*/
+/// Return ranges sorted by start: (start, end excluded, node)
+#[cfg(unix)]
+pub fn get_numa_nodes_ranges() -> Result<Vec<(Pfn, Pfn, u32)>, Box<dyn std::error::Error>> {
+    let block_size =
+        std::fs::read_to_string(root_path("/sys/devices/system/memory/block_size_bytes"))?;
+    let block_size = u64::from_str_radix(block_size.trim(), 16)?;
+    let pages_per_block = block_size / procfs::page_size();
+
+    let mut ranges = Vec::new();
+    let pattern = root_path("/sys/devices/system/node/node[0-9]*");
+    for node_dir in glob::glob(&pattern.to_string_lossy())? {
+        let node_dir = node_dir?;
+        let node: u32 = node_dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("node"))
+            .ok_or("Invalid NUMA node directory")?
+            .parse()?;
+
+        for entry in std::fs::read_dir(&node_dir)? {
+            let name = entry?.file_name();
+            // memory blocks are named memory<index>
+            let Some(Ok(block)) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("memory"))
+                .map(|block| block.parse::<u64>())
+            else {
+                continue;
+            };
+            ranges.push((
+                Pfn(block * pages_per_block),
+                Pfn((block + 1) * pages_per_block),
+                node,
+            ));
+        }
+    }
+    ranges.sort_by_key(|(start, _end, _node)| start.0);
+
+    Ok(ranges)
+}
+
+#[derive(Hash, Eq, PartialEq, Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct ShmReference {
     key: i32,
     shmid: u64,
 }
 
+/// Memory of a process
+///
+/// Splitters only use the values read during the scan, so processes can also be built without /proc,
+/// e.g. `ProcessInfo { pid: 1, uid: 1000, ..Default::default() }`
 #[cfg(unix)]
+#[derive(Serialize, Deserialize, Default)]
 pub struct ProcessInfo {
-    pub process: Process,
+    pub pid: i32,
+    /// None when loaded from a snapshot
+    #[serde(skip)]
+    pub process: Option<Process>,
+    pub comm: String,
     pub uid: u32,
+    /// Real primary group, from /proc/<pid>/status. Snapshots saved before it was read have 0
+    #[serde(default)]
+    pub gid: u32,
     pub environ: HashMap<OsString, OsString>,
     pub pfns: HashSet<Pfn, BuildHasherDefault<TheHash>>,
     pub anon_pfns: HashSet<Pfn, BuildHasherDefault<TheHash>>,
+    /// Swapped pages: (swap type, swap offset)
+    ///
+    /// From pagemap entries, type is bits 0-4 and offset is bits 5-54, both are returned by procfs as u64
+    /// without truncation. Offsets are per swap device, so a page is identified by both values
     pub swap_pages: HashSet<(u64, u64), BuildHasherDefault<TheHash>>,
     pub anon_swap_pages: HashSet<(u64, u64), BuildHasherDefault<TheHash>>,
     pub referenced_shms: HashSet<Shm>,
+    /// Resident pages of each mapped file
+    pub files_pfns: HashMap<PathBuf, HashSet<Pfn, BuildHasherDefault<TheHash>>>,
     pub rss: u64,
+    /// Part of rss in anonymous mappings (heap, stack...)
+    pub anon_rss: u64,
+    /// Part of rss in file-backed mappings
+    pub file_rss: u64,
+    /// Page cache mapped by this process, each page divided by the number of times it is mapped
+    pub page_cache_rss: u64,
+    /// Part of file_rss in mappings of deleted files, not visible on any filesystem
+    pub deleted_file_rss: u64,
+    pub pss: u64,
+    /// Swapped memory size
+    pub swap: u64,
+    /// Pages mapped only once, by this process
+    pub private_pages: u64,
+    /// Pages backed by huge pages (hugetlbfs or transparent)
+    pub huge_pages: u64,
+    /// Mapped pages outside of System RAM (device memory, e.g. GPU or DMA buffers), not counted in rss
+    pub device_pages: u64,
+    /// Pages that can't be reclaimed: mlocked, or otherwise unevictable
+    pub locked_pages: u64,
     pub vsz: u64,
+    pub anon_vsz: u64,
+    pub file_vsz: u64,
     pub pte: u64,
+    /// VmPTE was not in /proc/<pid>/status, pte is 0
+    pub pte_missing: bool,
+    /// Present pages with a PFN of 0 in pagemap: PFNs are hidden, and these pages are missing from rss
+    #[serde(default)]
+    pub hidden_pfn_pages: u64,
     pub fds: usize,
+    /// Number of mappings (VMAs), each one has a cost for the kernel
+    pub num_maps: usize,
     pub unknown_shm: HashSet<ShmReference>,
 }
 
@@ -530,8 +1010,52 @@ pub struct ProcessGroupInfo {
     pub swap_pages: HashSet<(u64, u64), BuildHasherDefault<TheHash>>,
     pub anon_swap_pages: HashSet<(u64, u64), BuildHasherDefault<TheHash>>,
     pub referenced_shm: HashSet<Shm>,
+    pub pss: u64,
+    /// Pages mapped only once, system wide
+    pub private_pages: u64,
+    /// Pages mapped multiple times, by this group or by others
+    pub shared_pages: u64,
+    /// Sum of processes RSS, pages shared by processes are counted multiple times
+    pub processes_rss: u64,
+    /// Sum of processes swap, pages shared by processes are counted multiple times
+    pub processes_swap: u64,
+    /// Already proportional, so it can be summed
+    pub page_cache_rss: u64,
+    /// Sum of processes device pages, pages shared by processes are counted multiple times
+    pub device_pages: u64,
+    /// Sum of processes virtual size, address spaces are not shared so it can't be deduplicated
+    pub vsz: u64,
     pub pte: u64,
     pub fds: usize,
+    pub num_maps: usize,
+}
+
+#[cfg(unix)]
+impl ProcessGroupInfo {
+    /// Add the processes and pages of another group, e.g. to roll up cgroups into their parent slice
+    /// Groups must not have processes in common, otherwise the sums count them twice
+    pub fn merge(&mut self, other: ProcessGroupInfo) {
+        self.processes_info.extend(other.processes_info);
+        self.pfns.extend(other.pfns);
+        self.anon_pfns.extend(other.anon_pfns);
+        self.swap_pages.extend(other.swap_pages);
+        self.anon_swap_pages.extend(other.anon_swap_pages);
+        self.referenced_shm.extend(other.referenced_shm);
+        self.pss += other.pss;
+        // private pages are mapped by only one process, so they can't be in both groups
+        self.private_pages += other.private_pages;
+        self.processes_rss += other.processes_rss;
+        self.processes_swap += other.processes_swap;
+        self.page_cache_rss += other.page_cache_rss;
+        self.device_pages += other.device_pages;
+        self.vsz += other.vsz;
+        self.pte += other.pte;
+        self.fds += other.fds;
+        self.num_maps += other.num_maps;
+
+        let resident_pages = self.pfns.iter().filter(|pfn| pfn.0 != 0).count() as u64;
+        self.shared_pages = resident_pages.saturating_sub(self.private_pages);
+    }
 }
 
 #[cfg(unix)]
@@ -550,8 +1074,17 @@ impl Debug for ProcessGroupInfo {
             .field("pfns", &self.pfns.len())
             .field("swap_pages", &self.swap_pages.len())
             .field("referenced_shm", &self.referenced_shm)
+            .field("pss", &self.pss)
+            .field("private_pages", &self.private_pages)
+            .field("shared_pages", &self.shared_pages)
+            .field("processes_rss", &self.processes_rss)
+            .field("processes_swap", &self.processes_swap)
+            .field("page_cache_rss", &self.page_cache_rss)
+            .field("device_pages", &self.device_pages)
+            .field("vsz", &self.vsz)
             .field("pte", &self.pte)
             .field("fds", &self.fds)
+            .field("num_maps", &self.num_maps)
             .finish()
     }
 }
@@ -564,21 +1097,212 @@ pub struct SmonInfo {
     pub large_pages: LargePages,
     pub processes: u64,
     pub pga_size: u64,
-    //sga_shm: Shm,
-    //sga_pfns: HashSet<Pfn>,
+    /// Segments attached by smon, filled by `attach_sga`
+    #[serde(skip)]
+    pub sga_shms: HashSet<Shm>,
+    /// Resident pages of the SGA segments
+    #[serde(skip)]
+    pub sga_pfns: HashSet<Pfn, BuildHasherDefault<TheHash>>,
+}
+
+impl SmonInfo {
+    /// Find the SGA segments and pages from the shm attached by smon
+    #[cfg(unix)]
+    pub fn attach_sga(
+        &mut self,
+        shms_metadata: &ShmsMetadata,
+        physical_pages: &PhysicalPages,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let process = Process::new(self.pid)?;
+        let Some(process_info) = get_process_info(process, shms_metadata, physical_pages)? else {
+            return Err(format!("smon {} is a zombie", self.pid).into());
+        };
+
+        self.sga_shms = process_info.referenced_shms;
+        self.sga_pfns = shms_metadata
+            .iter()
+            .filter(|(shm, _meta)| self.sga_shms.contains(shm))
+            .filter_map(|(_shm, meta)| meta.as_ref())
+            .flat_map(|(pfns, _swap_pages, _pages_4k, _huge_size)| pfns.iter().copied())
+            .collect();
+        Ok(())
+    }
+}
+
+/// Memory of the processes of an Oracle instance, with SGA pages counted once
+#[derive(Debug, Default)]
+pub struct SgaAttribution {
+    pub processes: usize,
+    /// Resident SGA, in bytes
+    pub sga: u64,
+    /// SGA pages also in the pagemap of processes, outside of their shm mappings, in bytes
+    pub double_counted: u64,
+    /// Pages of the processes that are not part of the SGA, in bytes
+    pub private_beyond_sga: u64,
 }
 
-// return info memory maps info for standard process or None for kernel process
+/// Processes of an instance are the ones with its ORACLE_SID
+#[cfg(unix)]
+pub fn sga_attribution(instance: &SmonInfo, processes_info: &[ProcessInfo]) -> SgaAttribution {
+    let oracle_sid = OsString::from("ORACLE_SID");
+    let processes: Vec<&ProcessInfo> = processes_info
+        .iter()
+        .filter(|p| p.environ.get(&oracle_sid) == Some(&instance.sid))
+        .collect();
+
+    let pfns: HashSet<Pfn, BuildHasherDefault<TheHash>> = processes
+        .iter()
+        .flat_map(|p| p.pfns.iter().copied())
+        .filter(|pfn| pfn.0 != 0)
+        .collect();
+    let double_counted = pfns.intersection(&instance.sga_pfns).count() as u64;
+
+    SgaAttribution {
+        processes: processes.len(),
+        sga: instance.sga_pfns.len() as u64 * procfs::page_size(),
+        double_counted: double_counted * procfs::page_size(),
+        private_beyond_sga: (pfns.len() as u64 - double_counted) * procfs::page_size(),
+    }
+}
+
+/// Attempts of `retry_proc` before giving up on a transient error
+const PROC_READ_ATTEMPTS: usize = 3;
+
+/// Whether a read of /proc failed on a transient error (interrupted or partial read), and can be tried again
+/// A vanished process (ESRCH, ENOENT) isn't a transient error
+pub fn is_transient_proc_error(e: &procfs::ProcError) -> bool {
+    match e {
+        procfs::ProcError::Incomplete(_) => true,
+        procfs::ProcError::Io(e, _) => matches!(
+            e.kind(),
+            std::io::ErrorKind::Interrupted
+                | std::io::ErrorKind::WouldBlock
+                | std::io::ErrorKind::UnexpectedEof
+        ),
+        _ => false,
+    }
+}
+
+/// Call `read` again on transient errors, up to `PROC_READ_ATTEMPTS` times
+pub fn retry_proc<T>(mut read: impl FnMut() -> procfs::ProcResult<T>) -> procfs::ProcResult<T> {
+    let mut attempt = 1;
+    loop {
+        match read() {
+            Err(e) if attempt < PROC_READ_ATTEMPTS && is_transient_proc_error(&e) => {
+                debug!("Transient error on attempt {attempt}: {e:?}");
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Same as `retry_proc`, for the files of process `pid`
+#[cfg(unix)]
+fn read_proc<T>(
+    pid: i32,
+    read: impl FnMut() -> procfs::ProcResult<T>,
+) -> Result<T, ProcStatsError> {
+    retry_proc(read).map_err(|e| ProcStatsError::from_proc(pid, e))
+}
+
+/// Kernel threads have the PF_KTHREAD flag
+#[cfg(unix)]
+pub fn is_kernel_thread(stat: &procfs::process::Stat) -> bool {
+    const PF_KTHREAD: u32 = 0x00200000;
+    stat.flags & PF_KTHREAD != 0
+}
+
+/// Zombies and kernel threads have no user memory to read
+#[cfg(unix)]
+fn is_memoryless(stat: &procfs::process::Stat) -> bool {
+    // memory of a zombie is already released
+    stat.state == 'Z' || is_kernel_thread(stat)
+}
+
+/// Kernel threads and zombies have no user memory, only report their name and owner
+/// They are still counted in their group, instead of disappearing from the report
+#[cfg(unix)]
+fn memoryless_process_info(
+    process: Process,
+    stat: procfs::process::Stat,
+) -> Result<Option<ProcessInfo>, ProcStatsError> {
+    Ok(Some(ProcessInfo {
+        pid: process.pid,
+        comm: stat.comm,
+        uid: read_proc(process.pid, || process.uid())?,
+        gid: read_proc(process.pid, || process.status())?.rgid,
+        process: Some(process),
+        ..Default::default()
+    }))
+}
+
+// return info memory maps info for standard process, or only name and owner for zombie process and kernel thread
 #[cfg(unix)]
 pub fn get_process_info(
     process: Process,
     shms_metadata: &ShmsMetadata,
-) -> Result<ProcessInfo, Box<dyn std::error::Error>> {
-    if process.cmdline()?.is_empty() {
-        // already handled in main
-        Err(String::from("No info for kernel process"))?
+    physical_pages: &PhysicalPages,
+) -> Result<Option<ProcessInfo>, ProcStatsError> {
+    let stat = read_proc(process.pid, || process.stat())?;
+    if is_memoryless(&stat) {
+        return memoryless_process_info(process, stat);
     }
 
+    let status = read_proc(process.pid, || process.status())?;
+    // page table size, missing for some processes (e.g. exiting)
+    let vmpte = status.vmpte;
+    let pte = vmpte.unwrap_or(0);
+
+    // file descriptors
+    let fds = read_proc(process.pid, || process.fd_count())?;
+
+    // proportional set size, computed by the kernel
+    let pss = match get_smaps_rollup(&process) {
+        Ok(fields) => fields.get("Pss").copied().unwrap_or(0),
+        Err(e) => {
+            debug!("Can't read smaps_rollup for pid {}: {e:?}", process.pid);
+            0
+        }
+    };
+
+    let memory_maps = crate::get_memory_maps_for_process(&process, true)?;
+    let maps_info = get_memory_maps_info(process.pid, &memory_maps, shms_metadata, physical_pages);
+
+    let comm = stat.comm;
+    let uid = read_proc(process.pid, || process.uid())?;
+    let env = read_proc(process.pid, || process.environ())?;
+
+    Ok(Some(ProcessInfo {
+        pid: process.pid,
+        process: Some(process),
+        comm,
+        uid,
+        environ: env,
+        gid: status.rgid,
+        pss,
+        pte,
+        pte_missing: vmpte.is_none(),
+        fds,
+        ..maps_info
+    }))
+}
+
+/// The kernel appends " (deleted)" to the path of mapped files that were unlinked
+pub fn is_deleted_path(path: &std::path::Path) -> bool {
+    path.to_string_lossy().ends_with("(deleted)")
+}
+
+/// Account pages of memory maps already read from /proc/<pid>/maps and /proc/<pid>/pagemap
+///
+/// Only fields computed from the maps are set. This doesn't read /proc, so maps can be built by hand
+#[cfg(unix)]
+pub fn get_memory_maps_info(
+    pid: i32,
+    memory_maps: &[(MemoryMap, Vec<PageInfo>)],
+    shms_metadata: &ShmsMetadata,
+    physical_pages: &PhysicalPages,
+) -> ProcessInfo {
     let page_size = procfs::page_size();
 
     // physical memory pages
@@ -590,27 +1314,35 @@ pub fn get_process_info(
 
     // size of pages in memory
     let mut rss = 0;
+    let mut anon_rss = 0;
+    let mut file_rss = 0;
+    let mut page_cache_rss = 0;
+    let mut deleted_file_rss = 0;
+    let mut device_pages = 0;
+    let mut hidden_pfn_pages = 0;
     // size of mappings
     let mut vsz = 0;
-
-    // page table size
-    let pte = process
-        .status()?
-        .vmpte
-        .expect("'vmpte' field does not exist");
-
-    // file descriptors
-    let fds = process.fd_count()?;
-
-    let memory_maps = crate::get_memory_maps_for_process(&process, true)?;
+    let mut anon_vsz = 0;
+    let mut file_vsz = 0;
 
     let mut referenced_shms = HashSet::new();
 
     let mut unknown_shm = HashSet::new();
 
+    let mut files_pfns: HashMap<PathBuf, HashSet<Pfn, BuildHasherDefault<TheHash>>> =
+        HashMap::new();
+
     for (memory_map, pages) in memory_maps.iter() {
         let size = memory_map.address.1 - memory_map.address.0;
         vsz += size;
+        hidden_pfn_pages += pages
+            .iter()
+            .filter(|page| {
+                matches!(page, PageInfo::MemoryPage(memory_page)
+                    if memory_page.get_page_flags().contains(MemoryPageFlags::PRESENT)
+                        && memory_page.get_page_frame_number().0 == 0)
+            })
+            .count() as u64;
         let _max_pages = size / page_size;
 
         match &memory_map.pathname {
@@ -632,18 +1364,32 @@ pub fn get_process_info(
                     });
                     warn!(
                         "Cant' find shm key {:?} shmid {:?} for pid {}",
-                        key, memory_map.inode, process.pid
+                        key, memory_map.inode, pid
                     );
                 }
             }
-            MMapPath::Path(_) => {
+            MMapPath::Path(path) => {
                 // not shm
+                file_vsz += size;
+                let deleted = is_deleted_path(path);
                 for page in pages.iter() {
                     match page {
                         PageInfo::MemoryPage(memory_page) => {
                             let pfn = memory_page.get_page_frame_number();
+                            if pfn.0 != 0 && !physical_pages.is_system_ram(pfn) {
+                                device_pages += 1;
+                                continue;
+                            }
                             if pfn.0 != 0 {
                                 rss += page_size;
+                                file_rss += page_size;
+                                // shared cache pages are attributed proportionally to their mappers
+                                let count = physical_pages.counts.get(&pfn).copied().unwrap_or(1);
+                                page_cache_rss += page_size / count.max(1);
+                                if deleted {
+                                    deleted_file_rss += page_size;
+                                }
+                                files_pfns.entry(path.clone()).or_default().insert(pfn);
                             }
                             pfns.insert(pfn);
                         }
@@ -659,12 +1405,18 @@ pub fn get_process_info(
             //MMapPath::Anonymous | MMapPath::Heap | MMapPath::Stack | MMapPath::TStack(_) => {
             _ => {
                 // Count as "anon"
+                anon_vsz += size;
                 for page in pages.iter() {
                     match page {
                         PageInfo::MemoryPage(memory_page) => {
                             let pfn = memory_page.get_page_frame_number();
+                            if pfn.0 != 0 && !physical_pages.is_system_ram(pfn) {
+                                device_pages += 1;
+                                continue;
+                            }
                             if pfn.0 != 0 {
                                 rss += page_size;
+                                anon_rss += page_size;
                             }
                             anon_pfns.insert(pfn);
                             pfns.insert(pfn);
@@ -682,24 +1434,275 @@ pub fn get_process_info(
         }
     } // end for memory_maps
 
-    let uid = process.uid()?;
-    let env = process.environ()?;
+    // a page mapped only once can only be mapped by this process
+    let private_pages = pfns
+        .iter()
+        .filter(|pfn| physical_pages.counts.get(*pfn) == Some(&1))
+        .count() as u64;
+
+    let huge_pages =
+        physical_pages.count_with_flags(&pfns, PhysicalPageFlags::HUGE | PhysicalPageFlags::THP);
+    let locked_pages = physical_pages.count_with_flags(
+        &pfns,
+        PhysicalPageFlags::MLOCKED | PhysicalPageFlags::UNEVICTABLE,
+    );
 
-    Ok(ProcessInfo {
-        process,
-        uid,
-        environ: env,
+    ProcessInfo {
+        pid,
         pfns,
         anon_pfns,
         referenced_shms,
+        files_pfns,
         swap_pages,
         anon_swap_pages,
         rss,
+        anon_rss,
+        file_rss,
+        page_cache_rss,
+        deleted_file_rss,
+        device_pages,
+        hidden_pfn_pages,
+        swap: swap_pages.len() as u64 * page_size,
+        private_pages,
+        huge_pages,
+        locked_pages,
+        vsz,
+        anon_vsz,
+        file_vsz,
+        num_maps: memory_maps.len(),
+        unknown_shm,
+        ..Default::default()
+    }
+}
+
+/// How processes memory is read
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// Totals from /proc/<pid>/smaps_rollup, see `get_process_info_fast`
+    Smaps,
+    /// Pages from /proc/<pid>/pagemap, see `get_process_info`
+    Pagemap,
+    /// Pagemap if PFNs can be read, smaps otherwise
+    Auto,
+}
+
+/// Whether pagemap gives PFNs: they are hidden without CAP_SYS_ADMIN, or by kernel lockdown
+#[cfg(unix)]
+pub fn pagemap_readable() -> bool {
+    let Ok(myself) = Process::myself() else {
+        return false;
+    };
+    match get_memory_maps_for_process(&myself, true) {
+        // at least our stack is resident
+        Ok(memory_maps) => memory_maps.iter().flat_map(|(_map, pages)| pages).any(|page| {
+            matches!(page, PageInfo::MemoryPage(memory_page) if memory_page.get_page_frame_number().0 != 0)
+        }),
+        Err(e) => {
+            debug!("Can't read our own pagemap: {e:?}");
+            false
+        }
+    }
+}
+
+/// Whether SysV shm segments can be attached to read their pages, tried on a segment of another user if any
+/// Attaching needs read permission on the segment, or CAP_IPC_OWNER
+#[cfg(unix)]
+fn shm_attachable() -> bool {
+    let shms = match procfs::SharedMemorySegments::from_file(root_path("/proc/sysvipc/shm")) {
+        Ok(shms) => shms.0,
+        Err(e) => {
+            debug!("Can't read /proc/sysvipc/shm: {e:?}");
+            return false;
+        }
+    };
+    let my_uid = uzers::get_effective_uid();
+    let Some(shm) = shms
+        .iter()
+        .find(|shm| u32::from(shm.uid) != my_uid)
+        .or_else(|| shms.first())
+    else {
+        // nothing to attach
+        return true;
+    };
+    unsafe {
+        let ptr = libc::shmat(shm.shmid as i32, core::ptr::null(), libc::SHM_RDONLY);
+        if ptr == -1i32 as *mut libc::c_void {
+            debug!(
+                "Can't attach shm {}: {}",
+                shm.shmid,
+                std::io::Error::last_os_error()
+            );
+            return false;
+        }
+        libc::shmdt(ptr);
+    }
+    true
+}
+
+/// What can be read with the current privileges, see `probe_permissions`
+#[derive(Debug, Clone, Copy)]
+pub struct Permissions {
+    /// PFNs in /proc/<pid>/pagemap, hidden without CAP_SYS_ADMIN
+    pub pagemap: bool,
+    /// /proc/kpageflags and /proc/kpagecount, only readable by root
+    pub kpageflags: bool,
+    /// Addresses of /proc/iomem, shown as 0 without CAP_SYS_ADMIN
+    pub iomem: bool,
+    /// smaps_rollup of processes of other users, needs CAP_SYS_PTRACE
+    pub other_processes: bool,
+    /// SysV shm segments of other users can be attached, see `shm_attachable`
+    pub shm: bool,
+}
+
+impl Permissions {
+    pub fn all(&self) -> bool {
+        self.pagemap && self.kpageflags && self.iomem && self.other_processes && self.shm
+    }
+}
+
+/// Try to read each source, so that missing privileges are explained before scanning instead of failing during it
+#[cfg(unix)]
+pub fn probe_permissions() -> Permissions {
+    let iomem = procfs::iomem().is_ok_and(|maps| {
+        maps.iter()
+            .any(|(_indent, map)| map.name == "System RAM" && map.address.1 != 0)
+    });
+
+    let my_uid = uzers::get_effective_uid();
+    let other_processes = match all_processes() {
+        Ok(processes) => processes
+            .flatten()
+            .find(|process| process.uid().is_ok_and(|uid| uid != my_uid))
+            // nothing to read
+            .map_or(true, |process| get_smaps_rollup(&process).is_ok()),
+        Err(_) => false,
+    };
+
+    Permissions {
+        pagemap: pagemap_readable(),
+        kpageflags: procfs::KPageFlags::new().is_ok() && procfs::KPageCount::new().is_ok(),
+        iomem,
+        other_processes,
+        shm: shm_attachable(),
+    }
+}
+
+/// Same as `get_process_info`, but only read the totals from /proc/<pid>/smaps_rollup instead of the pagemap
+/// Much faster, but pages are unknown: no PFNs, swap pages, or shm references
+#[cfg(unix)]
+pub fn get_process_info_fast(process: Process) -> Result<Option<ProcessInfo>, ProcStatsError> {
+    let stat = read_proc(process.pid, || process.stat())?;
+    if is_memoryless(&stat) {
+        return memoryless_process_info(process, stat);
+    }
+
+    let status = read_proc(process.pid, || process.status())?;
+    let pte = status.vmpte.unwrap_or(0);
+    // missing like VmPTE once the memory of an exiting process is released
+    let vsz = status.vmsize.unwrap_or(0) * 1024;
+    let fds = read_proc(process.pid, || process.fd_count())?;
+    let num_maps = read_proc(process.pid, || process.maps())?.len();
+
+    let fields = get_smaps_rollup(&process)?;
+    let field = |name: &str| fields.get(name).copied().unwrap_or(0);
+    let rss = field("Rss");
+    let anon_rss = field("Anonymous");
+
+    let comm = stat.comm;
+    let uid = read_proc(process.pid, || process.uid())?;
+    let environ = read_proc(process.pid, || process.environ())?;
+
+    Ok(Some(ProcessInfo {
+        pid: process.pid,
+        process: Some(process),
+        comm,
+        uid,
+        environ,
+        gid: status.rgid,
+        rss,
+        anon_rss,
+        file_rss: rss.saturating_sub(anon_rss),
+        // kernel >= 5.9
+        page_cache_rss: field("Pss_File"),
+        pss: field("Pss"),
+        swap: field("Swap"),
         vsz,
         pte,
+        pte_missing: status.vmpte.is_none(),
         fds,
-        unknown_shm,
-    })
+        num_maps,
+        ..Default::default()
+    }))
+}
+
+/// Find the processes attached to each shm segment, and add the segments to their `referenced_shms`
+///
+/// Attachments are found in /proc/<pid>/maps during the scan, but a process can also map pages of a segment
+/// without a matching mapping (e.g. unknown shm): a process is attached if its PFNs intersect the PFNs of
+/// the segment. Return the sorted pids attached to each segment
+#[cfg(unix)]
+pub fn attach_shms(
+    processes_info: &mut [ProcessInfo],
+    shms_metadata: &ShmsMetadata,
+) -> HashMap<Shm, Vec<i32>> {
+    // pages can be shared by multiple segments
+    let mut shms_by_pfn: HashMap<Pfn, Vec<Shm>, BuildHasherDefault<TheHash>> = HashMap::default();
+    for (shm, meta) in shms_metadata {
+        if let Some((pfns, _swap_pages, _pages_4k, _huge_size)) = meta {
+            for pfn in pfns {
+                shms_by_pfn.entry(*pfn).or_default().push(*shm);
+            }
+        }
+    }
+
+    let mut attachments: HashMap<Shm, Vec<i32>> = HashMap::new();
+    for process_info in processes_info.iter_mut() {
+        let found: HashSet<Shm> = process_info
+            .pfns
+            .iter()
+            .filter_map(|pfn| shms_by_pfn.get(pfn))
+            .flatten()
+            .copied()
+            .collect();
+        process_info.referenced_shms.extend(found);
+
+        for shm in &process_info.referenced_shms {
+            attachments.entry(*shm).or_default().push(process_info.pid);
+        }
+    }
+    for pids in attachments.values_mut() {
+        pids.sort_unstable();
+    }
+
+    attachments
+}
+
+/// Union of a set of each process, computed in parallel
+///
+/// Each rayon job fills its own set, then sets are merged pairwise, the smaller into the larger: no lock is
+/// shared between threads
+#[cfg(unix)]
+pub fn par_union<'a, T, F>(
+    processes_info: &'a [ProcessInfo],
+    set: F,
+) -> HashSet<T, BuildHasherDefault<TheHash>>
+where
+    T: Eq + std::hash::Hash + Copy + Send + Sync + 'a,
+    F: Fn(&'a ProcessInfo) -> &'a HashSet<T, BuildHasherDefault<TheHash>> + Send + Sync,
+{
+    processes_info
+        .par_iter()
+        .fold(HashSet::default, |mut union, process_info| {
+            union.extend(set(process_info));
+            union
+        })
+        .reduce(HashSet::default, |mut a, mut b| {
+            if a.len() < b.len() {
+                std::mem::swap(&mut a, &mut b);
+            }
+            a.extend(b);
+            a
+        })
 }
 
 #[cfg(unix)]
@@ -708,25 +1711,43 @@ pub fn get_processes_group_info(
     name: &str,
     _shms_metadata: &ShmsMetadata,
 ) -> ProcessGroupInfo {
-    let mut pfns: HashSet<Pfn, BuildHasherDefault<TheHash>> = HashSet::default();
-    let mut anon_pfns: HashSet<Pfn, BuildHasherDefault<TheHash>> = HashSet::default();
-    let mut swap_pages: HashSet<(u64, u64), BuildHasherDefault<TheHash>> = HashSet::default();
-    let mut anon_swap_pages: HashSet<(u64, u64), BuildHasherDefault<TheHash>> = HashSet::default();
     let mut referenced_shm = HashSet::new();
+    let mut pss = 0;
+    let mut private_pages = 0;
+    let mut processes_rss = 0;
+    let mut processes_swap = 0;
+    let mut page_cache_rss = 0;
+    let mut device_pages = 0;
+    let mut vsz = 0;
     let mut pte = 0;
     let mut fds = 0;
+    let mut num_maps = 0;
+
+    let pfns = par_union(&processes_info, |p| &p.pfns);
+    let anon_pfns = par_union(&processes_info, |p| &p.anon_pfns);
+    let swap_pages = par_union(&processes_info, |p| &p.swap_pages);
+    let anon_swap_pages = par_union(&processes_info, |p| &p.anon_swap_pages);
 
     for process_info in &processes_info {
-        pfns.par_extend(&process_info.pfns);
-        anon_pfns.par_extend(&process_info.anon_pfns);
-        swap_pages.par_extend(&process_info.swap_pages);
-        anon_swap_pages.par_extend(&process_info.anon_swap_pages);
         referenced_shm.extend(&process_info.referenced_shms);
+        // PSS is already shared between processes, so it can be summed
+        pss += process_info.pss;
+        // private pages can't be referenced by 2 processes
+        private_pages += process_info.private_pages;
+        processes_rss += process_info.rss;
+        processes_swap += process_info.swap;
+        page_cache_rss += process_info.page_cache_rss;
+        device_pages += process_info.device_pages;
+        vsz += process_info.vsz;
         // TODO: we can't sum PTE, this a theorical max value
         pte += process_info.pte;
         fds += process_info.fds;
+        num_maps += process_info.num_maps;
     }
 
+    let resident_pages = pfns.iter().filter(|pfn| pfn.0 != 0).count() as u64;
+    let shared_pages = resident_pages.saturating_sub(private_pages);
+
     ProcessGroupInfo {
         name: name.to_string(),
         processes_info,
@@ -735,23 +1756,38 @@ pub fn get_processes_group_info(
         swap_pages,
         anon_swap_pages,
         referenced_shm,
+        pss,
+        private_pages,
+        shared_pages,
+        processes_rss,
+        processes_swap,
+        page_cache_rss,
+        device_pages,
+        vsz,
         pte,
         fds,
+        num_maps,
     }
 }
 
 /// Spawn new process with database user
 /// return smon info
+///
+/// The subprocess is our own executable running the hidden `get-db-info --pid <pid>` command, as `uid` with its
+/// groups, and ORACLE_SID, ORACLE_HOME and LD_LIBRARY_PATH set for the instance. It refuses to run as root, and
+/// must exit 0 after printing a single `SmonInfo` as JSON on stdout
 #[cfg(unix)]
 pub fn get_smon_info(
     pid: i32,
     uid: u32,
     sid: &OsStr,
     home: &OsStr,
-) -> Result<SmonInfo, Box<dyn std::error::Error>> {
-    let myself = std::env::current_exe()?;
+) -> Result<SmonInfo, ProcStatsError> {
+    let myself = std::env::current_exe()
+        .map_err(|e| ProcStatsError::SmonSpawn(format!("Can't find our executable: {e}")))?;
 
-    let user = uzers::get_user_by_uid(uid).expect("Can't find user for uid {uid}");
+    let user = uzers::get_user_by_uid(uid)
+        .ok_or_else(|| ProcStatsError::SmonSpawn(format!("Can't find user for uid {uid}")))?;
     let gid = user.primary_group_id();
 
     let mut lib = home.to_os_string();
@@ -771,18 +1807,282 @@ pub fn get_smon_info(
         let groups: Vec<u32> = groups.iter().map(|g| g.gid()).collect();
         cmd.groups(&groups);
     }
-    let child = cmd.spawn()?;
-    let output = child.wait_with_output()?;
+    let output = cmd
+        .spawn()
+        .and_then(|child| child.wait_with_output())
+        .map_err(|e| {
+            ProcStatsError::SmonSpawn(format!("Can't run subprocess for DB {sid:?}: {e}"))
+        })?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
 
     if !output.status.success() {
-        return Err(format!(
-            "Proces failed for DB {sid:?} {uid} {home:?}: {:?}",
-            output
-        ))?;
+        return Err(ProcStatsError::SmonSpawn(format!(
+            "Process failed for DB {sid:?} {uid} {home:?}: {}, stdout: {stdout:?}, stderr: {stderr:?}",
+            output.status
+        )));
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-
-    let smon_info: SmonInfo = serde_json::from_str(&stdout)?;
+    let smon_info: SmonInfo = serde_json::from_str(stdout.trim()).map_err(|e| {
+        ProcStatsError::Parse(format!(
+            "DB info for {sid:?}: {e}, stdout: {stdout:?}, stderr: {stderr:?}"
+        ))
+    })?;
     Ok(smon_info)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use procfs::process::MemoryMaps;
+
+    /// Pagemap entry of a present page
+    fn present(pfn: u64) -> PageInfo {
+        PageInfo::parse_info(1 << 63 | pfn)
+    }
+
+    /// Pagemap entry of a swapped page
+    fn swapped(swap_type: u64, offset: u64) -> PageInfo {
+        PageInfo::parse_info(1 << 62 | offset << 5 | swap_type)
+    }
+
+    fn memory_map(line: &str) -> MemoryMap {
+        MemoryMaps::from_read(line.as_bytes())
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap()
+    }
+
+    /// Processes built without /proc, e.g. `ProcessInfo::test(1).with_uid(1000).with_pfns(&[1, 2])`
+    /// Shared by the tests of all modules
+    impl ProcessInfo {
+        pub(crate) fn test(pid: i32) -> Self {
+            ProcessInfo {
+                pid,
+                ..Default::default()
+            }
+        }
+
+        pub(crate) fn with_uid(self, uid: u32) -> Self {
+            ProcessInfo { uid, ..self }
+        }
+
+        pub(crate) fn with_gid(self, gid: u32) -> Self {
+            ProcessInfo { gid, ..self }
+        }
+
+        pub(crate) fn with_comm(self, comm: &str) -> Self {
+            ProcessInfo {
+                comm: comm.to_string(),
+                ..self
+            }
+        }
+
+        pub(crate) fn with_env(mut self, var: &str, value: &str) -> Self {
+            self.environ.insert(var.into(), value.into());
+            self
+        }
+
+        pub(crate) fn with_pfns(self, pfns: &[u64]) -> Self {
+            ProcessInfo {
+                pfns: pfns.iter().copied().map(Pfn).collect(),
+                ..self
+            }
+        }
+
+        pub(crate) fn with_swap_pages(self, swap_pages: &[(u64, u64)]) -> Self {
+            ProcessInfo {
+                swap_pages: swap_pages.iter().copied().collect(),
+                ..self
+            }
+        }
+    }
+
+    /// /proc/<pid>/stat of a process, the fields after flags are 0
+    fn stat(pid: i32, comm: &str, state: char, flags: u32) -> procfs::process::Stat {
+        let line = format!(
+            "{pid} ({comm}) {state} 1 {pid} {pid} 0 -1 {flags} {}",
+            ["0"; 43].join(" ")
+        );
+        procfs::process::Stat::from_read(line.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn memory_maps_info() {
+        let page_size = procfs::page_size();
+        let memory_maps = vec![
+            (
+                memory_map("7f0000000000-7f0000004000 rw-p 00000000 00:00 0"),
+                vec![
+                    present(100),
+                    present(101),
+                    swapped(1, 7),
+                    PageInfo::parse_info(0),
+                ],
+            ),
+            (
+                memory_map("7f0000010000-7f0000012000 r--p 00000000 08:01 1234 /usr/lib/libc.so.6"),
+                vec![present(101), present(200)],
+            ),
+        ];
+        let physical_pages = PhysicalPages {
+            counts: HashMap::from([(Pfn(100), 1), (Pfn(101), 2), (Pfn(200), 4)]),
+            ..Default::default()
+        };
+
+        let info =
+            get_memory_maps_info(42, &memory_maps, &ShmsMetadata::default(), &physical_pages);
+
+        assert_eq!(info.pid, 42);
+        assert_eq!(info.vsz, 0x6000);
+        assert_eq!(info.anon_vsz, 0x4000);
+        assert_eq!(info.file_vsz, 0x2000);
+        // pfn 101 is mapped twice, rss counts mappings
+        assert_eq!(info.rss, 4 * page_size);
+        assert_eq!(info.anon_rss, 2 * page_size);
+        assert_eq!(info.file_rss, 2 * page_size);
+        assert_eq!(info.page_cache_rss, page_size / 2 + page_size / 4);
+        // the page that isn't present is kept as pfn 0, groups skip it
+        let pfns: BTreeSet<u64> = info.pfns.iter().map(|pfn| pfn.0).collect();
+        assert_eq!(pfns, BTreeSet::from([0, 100, 101, 200]));
+        assert_eq!(info.private_pages, 1);
+        assert_eq!(info.hidden_pfn_pages, 0);
+        assert_eq!(info.swap_pages.iter().copied().collect_vec(), [(1, 7)]);
+        assert_eq!(info.anon_swap_pages.len(), 1);
+        assert_eq!(info.swap, page_size);
+        assert_eq!(info.num_maps, 2);
+    }
+
+    #[test]
+    fn retry_proc_transient_error() {
+        let mut calls = 0;
+        let result = retry_proc(|| {
+            calls += 1;
+            match calls {
+                1 => Err(procfs::ProcError::Io(
+                    std::io::ErrorKind::Interrupted.into(),
+                    None,
+                )),
+                _ => Ok(calls),
+            }
+        });
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn retry_proc_vanished_process() {
+        let mut calls = 0;
+        let result: procfs::ProcResult<()> = retry_proc(|| {
+            calls += 1;
+            Err(procfs::ProcError::NotFound(None))
+        });
+        assert!(matches!(result, Err(procfs::ProcError::NotFound(_))));
+        assert_eq!(calls, 1);
+
+        let mut calls = 0;
+        let result: procfs::ProcResult<()> = retry_proc(|| {
+            calls += 1;
+            Err(procfs::ProcError::Io(
+                std::io::Error::from_raw_os_error(libc::ESRCH),
+                None,
+            ))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn retry_proc_gives_up() {
+        let mut calls = 0;
+        let result: procfs::ProcResult<()> = retry_proc(|| {
+            calls += 1;
+            Err(procfs::ProcError::Incomplete(None))
+        });
+        assert!(matches!(result, Err(procfs::ProcError::Incomplete(_))));
+        assert_eq!(calls, PROC_READ_ATTEMPTS);
+    }
+
+    #[test]
+    fn swap_pages_dedup() {
+        // would collide if offsets were truncated to 32 bits
+        let high = (1 << 32) + 5;
+        let memory_maps = vec![(
+            memory_map("7f0000000000-7f0000003000 rw-p 00000000 00:00 0"),
+            vec![swapped(0, 5), swapped(1, 5), swapped(0, high)],
+        )];
+        let info = get_memory_maps_info(
+            1,
+            &memory_maps,
+            &ShmsMetadata::default(),
+            &PhysicalPages::default(),
+        );
+        let swap_pages: BTreeSet<(u64, u64)> = info.swap_pages.iter().copied().collect();
+        assert_eq!(swap_pages, BTreeSet::from([(0, 5), (0, high), (1, 5)]));
+
+        let processes = vec![
+            info,
+            ProcessInfo::test(2).with_swap_pages(&[(0, 5), (1, high)]),
+        ];
+        let mut group = get_processes_group_info(processes, "a", &ShmsMetadata::default());
+        assert_eq!(group.swap_pages.len(), 4);
+
+        let other = get_processes_group_info(
+            vec![ProcessInfo::test(3).with_swap_pages(&[(1, 5), (2, 5)])],
+            "b",
+            &ShmsMetadata::default(),
+        );
+        group.merge(other);
+        assert_eq!(group.swap_pages.len(), 5);
+        assert_eq!(group.processes_info.len(), 3);
+    }
+
+    #[test]
+    fn memoryless_processes_are_kept() {
+        const PF_KTHREAD: u32 = 0x00200000;
+        assert!(is_memoryless(&stat(2, "kthreadd", 'S', PF_KTHREAD)));
+        assert!(!is_memoryless(&stat(100, "bash", 'S', 0)));
+
+        // the zombie branch of `get_process_info`, on a stat parsed as a zombie's
+        let myself = Process::myself().unwrap();
+        let zombie_stat = stat(myself.pid, "defunct", 'Z', 0);
+        assert!(is_memoryless(&zombie_stat));
+        let zombie = memoryless_process_info(myself, zombie_stat)
+            .unwrap()
+            .unwrap();
+        assert_eq!(zombie.comm, "defunct");
+        assert_eq!(zombie.uid, uzers::get_effective_uid());
+        let zombie_pid = zombie.pid;
+
+        let page_size = procfs::page_size();
+        let memory_maps = vec![(
+            memory_map("7f0000000000-7f0000002000 rw-p 00000000 00:00 0"),
+            vec![swapped(0, 10), swapped(0, 11)],
+        )];
+        let swapped_out = ProcessInfo {
+            pte: 8192,
+            fds: 4,
+            ..get_memory_maps_info(
+                2,
+                &memory_maps,
+                &ShmsMetadata::default(),
+                &PhysicalPages::default(),
+            )
+        };
+        assert!(swapped_out.pfns.is_empty());
+        assert_eq!(swapped_out.rss, 0);
+
+        let group =
+            get_processes_group_info(vec![zombie, swapped_out], "group", &ShmsMetadata::default());
+        let pids: BTreeSet<i32> = group.processes_info.iter().map(|p| p.pid).collect();
+        assert_eq!(pids, BTreeSet::from([zombie_pid, 2]));
+        assert!(group.pfns.is_empty());
+        assert_eq!(group.processes_rss, 0);
+        assert_eq!(group.swap_pages.len(), 2);
+        assert_eq!(group.processes_swap, 2 * page_size);
+        assert_eq!(group.pte, 8192);
+        assert_eq!(group.fds, 4);
+    }
+}
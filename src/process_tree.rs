@@ -1,14 +1,33 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::os::unix::fs::MetadataExt;
 
 use procfs::process::Process;
+use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize)]
 pub struct ProcessTree {
     edges: Vec<(i32, i32)>,
+    /// pid -> inode of its PID namespace, missing if /proc/<pid>/ns/pid can't be read
+    #[serde(default)]
+    pid_namespaces: HashMap<i32, u64>,
+    /// PID namespace of memstats itself
+    #[serde(default)]
+    my_pid_namespace: Option<u64>,
+}
+
+/// Inode of the PID namespace of a process
+pub fn pid_namespace(pid: &str) -> Option<u64> {
+    let metadata = std::fs::metadata(crate::root_path(&format!("/proc/{pid}/ns/pid"))).ok()?;
+    Some(metadata.ino())
 }
 
 impl ProcessTree {
     pub fn new(all_processes: &[Process]) -> Self {
-        let mut tree = ProcessTree { edges: Vec::new() };
+        let mut tree = ProcessTree {
+            edges: Vec::new(),
+            pid_namespaces: HashMap::new(),
+            my_pid_namespace: pid_namespace("self"),
+        };
 
         for p in all_processes.iter() {
             let pid = p.pid;
@@ -18,11 +37,47 @@ impl ProcessTree {
             };
 
             tree.edges.push((ppid, pid));
+            if let Some(namespace) = pid_namespace(&pid.to_string()) {
+                tree.pid_namespaces.insert(pid, namespace);
+            }
         }
 
         tree
     }
 
+    /// Whether both processes are known to live in different PID namespaces
+    /// Parents of a namespace init are outside of it, and its pid 1 is not the init of the system
+    pub fn crosses_pid_namespace(&self, pid: i32, other: i32) -> bool {
+        match (
+            self.pid_namespaces.get(&pid),
+            self.pid_namespaces.get(&other),
+        ) {
+            (Some(namespace), Some(other_namespace)) => namespace != other_namespace,
+            _ => false,
+        }
+    }
+
+    /// Whether the process is known to live in another PID namespace than memstats
+    pub fn in_other_pid_namespace(&self, pid: i32) -> bool {
+        match (self.pid_namespaces.get(&pid), self.my_pid_namespace) {
+            (Some(namespace), Some(my_namespace)) => *namespace != my_namespace,
+            _ => false,
+        }
+    }
+
+    /// Number of processes known to live in another PID namespace than memstats
+    pub fn other_pid_namespace_count(&self) -> usize {
+        self.pid_namespaces
+            .keys()
+            .filter(|pid| self.in_other_pid_namespace(**pid))
+            .count()
+    }
+
+    /// pid -> parent pid
+    pub fn parents(&self) -> HashMap<i32, i32> {
+        self.edges.iter().map(|&(ppid, pid)| (pid, ppid)).collect()
+    }
+
     pub fn ancestors(&self, pid: i32, include_first: bool) -> Vec<i32> {
         let mut ancestors = if include_first { vec![pid] } else { Vec::new() };
 
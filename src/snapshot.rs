@@ -0,0 +1,69 @@
+//! Save scanned processes to a file, to report on them later without reading /proc
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use anyhow::Context;
+use procfs::{process::Pfn, Shm};
+use serde::{Deserialize, Serialize};
+
+use crate::{process_tree::ProcessTree, PhysicalPages, ProcessInfo, ShmsMetadata};
+
+type ShmMetadata = Option<(HashSet<Pfn>, HashSet<(u64, u64)>, usize, usize)>;
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    processes_info: &'a [ProcessInfo],
+    tree: &'a ProcessTree,
+    physical_pages: &'a PhysicalPages,
+    // JSON keys must be strings, store shm as a list
+    shms_metadata: Vec<(&'a Shm, &'a ShmMetadata)>,
+}
+
+/// Everything needed to split processes into groups, and report on them
+#[derive(Deserialize)]
+pub struct Snapshot {
+    pub processes_info: Vec<ProcessInfo>,
+    pub tree: ProcessTree,
+    pub physical_pages: PhysicalPages,
+    shms_metadata: Vec<(Shm, ShmMetadata)>,
+}
+
+impl Snapshot {
+    pub fn save(
+        path: &Path,
+        processes_info: &[ProcessInfo],
+        tree: &ProcessTree,
+        physical_pages: &PhysicalPages,
+        shms_metadata: &ShmsMetadata,
+    ) -> anyhow::Result<()> {
+        let snapshot = SnapshotRef {
+            processes_info,
+            tree,
+            physical_pages,
+            shms_metadata: shms_metadata.iter().collect(),
+        };
+
+        let file = File::create(path).with_context(|| format!("Can't create {path:?}"))?;
+        serde_json::to_writer(BufWriter::new(file), &snapshot)
+            .with_context(|| format!("Can't write snapshot to {path:?}"))?;
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path).with_context(|| format!("Can't open {path:?}"))?;
+        let snapshot = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Can't read snapshot from {path:?}"))?;
+
+        Ok(snapshot)
+    }
+
+    pub fn shms_metadata(&self) -> ShmsMetadata {
+        self.shms_metadata.iter().cloned().collect()
+    }
+}
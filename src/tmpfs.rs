@@ -1,4 +1,5 @@
 use log::warn;
+use std::sync::OnceLock;
 use tabled::Tabled;
 
 #[derive(Tabled)]
@@ -6,19 +7,85 @@ pub struct TmpfsMetadata {
     /// Mount point
     pub mount_point: String,
     /// FS size in Bytes
-    #[tabled(display_with = "format_units_MiB")]
+    #[tabled(display_with = "format_size")]
     pub fs_size: u64,
     /// Free space in Bytes
-    #[tabled(display_with = "format_units_MiB")]
+    #[tabled(display_with = "format_size")]
     pub fs_used: u64,
 }
 
-pub fn format_units_MiB(value: &u64) -> String {
-    let format = humansize::FormatSizeOptions::from(humansize::DECIMAL)
-        .fixed_at(Some(humansize::FixedAt::Mega));
+/// Unit used to display sizes
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Unit {
+    #[value(name = "bytes")]
+    Bytes,
+    #[value(name = "KiB")]
+    KiB,
+    #[value(name = "MiB")]
+    MiB,
+    #[value(name = "GiB")]
+    GiB,
+    /// Pick a unit for each value
+    #[value(name = "auto")]
+    Auto,
+}
+
+static UNIT: OnceLock<Unit> = OnceLock::new();
+
+/// Set the unit used by `format_size`, only the first call has an effect
+pub fn set_unit(unit: Unit) {
+    if UNIT.set(unit).is_err() {
+        warn!("Unit is already set");
+    }
+}
+
+/// Base used to display PFNs
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PfnFormat {
+    /// 0x-prefixed hexadecimal
+    Hex,
+    /// Decimal, e.g. to seek in /proc/kpageflags with `dd skip=`
+    Dec,
+}
+
+static PFN_FORMAT: OnceLock<PfnFormat> = OnceLock::new();
+
+/// Set the base used by `format_pfn`, only the first call has an effect
+pub fn set_pfn_format(pfn_format: PfnFormat) {
+    if PFN_FORMAT.set(pfn_format).is_err() {
+        warn!("PFN format is already set");
+    }
+}
+
+/// Format a PFN with the base from `set_pfn_format`, hexadecimal by default
+pub fn format_pfn(pfn: u64) -> String {
+    match PFN_FORMAT.get().copied().unwrap_or(PfnFormat::Hex) {
+        PfnFormat::Hex => format!("{pfn:#x}"),
+        PfnFormat::Dec => format!("{pfn}"),
+    }
+}
+
+/// Format a size in bytes with the unit from `set_unit`, MiB by default
+pub fn format_size(value: &u64) -> String {
+    let format = humansize::FormatSizeOptions::from(humansize::BINARY);
+    let format = match UNIT.get().copied().unwrap_or(Unit::MiB) {
+        Unit::Bytes => return format!("{value} B"),
+        Unit::KiB => format.fixed_at(Some(humansize::FixedAt::Kilo)),
+        Unit::MiB => format.fixed_at(Some(humansize::FixedAt::Mega)),
+        Unit::GiB => format.fixed_at(Some(humansize::FixedAt::Giga)),
+        Unit::Auto => format,
+    };
     humansize::format_size(*value, format)
 }
 
+/// `format_size`, or "-" for an unknown size
+pub fn format_optional_size(value: &Option<u64>) -> String {
+    match value {
+        Some(value) => format_size(value),
+        None => "-".to_string(),
+    }
+}
+
 pub fn display_tmpfs() {
     println!("Scanning tmpfs...");
     let mountinfos = procfs::process::Process::myself().unwrap().mountinfo();
@@ -0,0 +1,43 @@
+//! `get-db-info` is the subprocess spawned by `snap::get_smon_info`: it must refuse to run as root, and
+//! print a single `SmonInfo` as JSON then exit 0
+
+use std::process::Command;
+
+use snap::SmonInfo;
+
+fn get_db_info(pid: i32) -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_memstats"));
+    cmd.args(["get-db-info", "--pid", &pid.to_string()]);
+    cmd
+}
+
+#[test]
+fn refused_as_root() {
+    if uzers::get_effective_uid() != 0 {
+        eprintln!("Not running as root, skipped");
+        return;
+    }
+    let output = get_db_info(1)
+        .env_clear()
+        .env("ORACLE_SID", "TEST")
+        .output()
+        .expect("Can't run memstats");
+    assert!(!output.status.success(), "{output:?}");
+    assert!(output.stdout.is_empty(), "{output:?}");
+}
+
+#[test]
+#[ignore = "needs an Oracle instance: run as its owner, with ORACLE_SID, ORACLE_HOME and LD_LIBRARY_PATH set"]
+fn prints_smon_info() {
+    let sid = std::env::var_os("ORACLE_SID").expect("Missing ORACLE_SID");
+    let output = get_db_info(1234).output().expect("Can't run memstats");
+    assert!(output.status.success(), "{output:?}");
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 1, "{stdout:?}");
+    let smon_info: SmonInfo = serde_json::from_str(stdout.trim()).unwrap();
+    assert_eq!(smon_info.pid, 1234);
+    assert_eq!(smon_info.sid, sid);
+    assert!(smon_info.sga_size > 0);
+    assert!(smon_info.processes > 0);
+}